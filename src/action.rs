@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// Everything a button can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    BrightUp,
+    BrightDown,
+    DarkOn,
+    DarkOff,
+    ScreenToggle,
+    VolUp,
+    VolDown,
+    Mute,
+    VolNorm,
+    PerfMax,
+    PerfNorm,
+    PerfCycle,
+    WifiOn,
+    WifiOff,
+    BluetoothOn,
+    BluetoothOff,
+    Suspend,
+    PowerOff,
+}
+
+pub static ACTIONS: [Action; 18] = [
+    Action::BrightUp,
+    Action::BrightDown,
+    Action::DarkOn,
+    Action::DarkOff,
+    Action::ScreenToggle,
+    Action::VolUp,
+    Action::VolDown,
+    Action::Mute,
+    Action::VolNorm,
+    Action::PerfMax,
+    Action::PerfNorm,
+    Action::PerfCycle,
+    Action::WifiOn,
+    Action::WifiOff,
+    Action::BluetoothOn,
+    Action::BluetoothOff,
+    Action::Suspend,
+    Action::PowerOff,
+];
+
+impl Action {
+    /// Name used for the action in configuration files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::BrightUp => "bright_up",
+            Action::BrightDown => "bright_down",
+            Action::DarkOn => "dark_on",
+            Action::DarkOff => "dark_off",
+            Action::ScreenToggle => "screen_toggle",
+            Action::VolUp => "vol_up",
+            Action::VolDown => "vol_down",
+            Action::Mute => "mute",
+            Action::VolNorm => "vol_norm",
+            Action::PerfMax => "perf_max",
+            Action::PerfNorm => "perf_norm",
+            Action::PerfCycle => "perf_cycle",
+            Action::WifiOn => "wifi_on",
+            Action::WifiOff => "wifi_off",
+            Action::BluetoothOn => "bluetooth_on",
+            Action::BluetoothOff => "bluetooth_off",
+            Action::Suspend => "suspend",
+            Action::PowerOff => "power_off",
+        }
+    }
+
+    /// Whether the action steps a level and keeps going while its buttons
+    /// are held.
+    pub fn repeats(self) -> bool {
+        matches!(self, Action::BrightUp | Action::BrightDown | Action::VolUp | Action::VolDown)
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub static SYS_BACKLIGHT_DIR: &str = "/sys/class/backlight";
+
+/// Screen brightness control, in percent of the maximum brightness.
+pub trait Backlight: Send + Sync {
+    fn brightness(&self) -> io::Result<u32>;
+    fn set_brightness(&self, percent: u32) -> io::Result<()>;
+    /// What the brightness is read from, for the logs.
+    fn describe(&self) -> String;
+}
+
+/// A backlight device of the kernel, driven through its `brightness` and
+/// `max_brightness` attributes.
+pub struct SysfsBacklight {
+    dir: PathBuf,
+    max: u32,
+}
+
+/// Runs `brightnessctl`, for kernels whose backlight isn't writable by
+/// ogage.
+pub struct BrightnessCtl;
+
+impl SysfsBacklight {
+    /// Opens the backlight `name` in `root`, usually `/sys/class/backlight`,
+    /// or the first one there when no name is given.
+    pub fn open(root: &Path, name: Option<&str>) -> io::Result<SysfsBacklight> {
+        let dir = match name {
+            Some(name) => root.join(name),
+            None => {
+                let mut dirs: Vec<PathBuf> = fs::read_dir(root)?
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .collect();
+                dirs.sort();
+                dirs.into_iter()
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no backlight in {}", root.display())))?
+            }
+        };
+
+        let max = read_number(&dir.join("max_brightness"))?;
+        if max == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no brightness levels", dir.display()),
+            ));
+        }
+
+        Ok(SysfsBacklight { dir, max })
+    }
+}
+
+impl Backlight for SysfsBacklight {
+    fn brightness(&self) -> io::Result<u32> {
+        let raw = read_number(&self.dir.join("brightness"))?;
+        Ok(((raw as u64 * 100 + self.max as u64 / 2) / self.max as u64) as u32)
+    }
+
+    fn set_brightness(&self, percent: u32) -> io::Result<()> {
+        let raw = (percent.min(100) as u64 * self.max as u64 + 50) / 100;
+        fs::write(self.dir.join("brightness"), raw.to_string())
+    }
+
+    fn describe(&self) -> String {
+        format!("{} (max {})", self.dir.display(), self.max)
+    }
+}
+
+impl Backlight for BrightnessCtl {
+    fn brightness(&self) -> io::Result<u32> {
+        let output = Command::new("brightnessctl").arg("-m").stdout(Stdio::piped()).output()?;
+        let brightness_str = String::from_utf8_lossy(&output.stdout);
+
+        // device,class,current,percent%,max
+        brightness_str
+            .split(&[',', '%'][..])
+            .nth(3)
+            .and_then(|percent| percent.trim().parse::<u32>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unexpected brightnessctl output"))
+    }
+
+    fn set_brightness(&self, percent: u32) -> io::Result<()> {
+        let brightness_str = percent.to_string() + "%";
+        let output = Command::new("brightnessctl").args(["s", &brightness_str]).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "brightnessctl {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        "brightnessctl".to_string()
+    }
+}
+
+/// Picks the backlight from the `backlight` setting: `brightnessctl`, the
+/// name of a device in `/sys/class/backlight`, or nothing for the first
+/// device found there. brightnessctl stands in when the device can't be used.
+pub fn select(setting: Option<&str>) -> Box<dyn Backlight> {
+    let name = match setting.map(|setting| setting.trim()) {
+        Some("brightnessctl") => return Box::new(BrightnessCtl),
+        Some("") | Some("auto") | None => None,
+        Some(name) => Some(name),
+    };
+
+    match SysfsBacklight::open(Path::new(SYS_BACKLIGHT_DIR), name) {
+        Ok(backlight) => Box::new(backlight),
+        Err(e) => {
+            println!("Can't use the sysfs backlight ({}), falling back to brightnessctl", e);
+            Box::new(BrightnessCtl)
+        }
+    }
+}
+
+fn read_number(path: &Path) -> io::Result<u32> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sysfs backlight class directory in the temporary directory, removed
+    /// when dropped.
+    struct FakeSysfs(PathBuf);
+
+    impl FakeSysfs {
+        fn new(test: &str, devices: &[(&str, &str, &str)]) -> FakeSysfs {
+            let root = std::env::temp_dir().join(format!("ogage-backlight-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for (name, brightness, max) in devices {
+                let dir = root.join(name);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("brightness"), brightness).unwrap();
+                fs::write(dir.join("max_brightness"), max).unwrap();
+            }
+            FakeSysfs(root)
+        }
+
+        fn raw(&self, name: &str) -> String {
+            fs::read_to_string(self.0.join(name).join("brightness")).unwrap()
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn scales_to_percent() {
+        let sysfs = FakeSysfs::new("scale", &[("panel", "128\n", "255\n")]);
+        let backlight = SysfsBacklight::open(&sysfs.0, Some("panel")).unwrap();
+        assert_eq!(backlight.brightness().unwrap(), 50);
+
+        backlight.set_brightness(100).unwrap();
+        assert_eq!(sysfs.raw("panel"), "255");
+        backlight.set_brightness(10).unwrap();
+        assert_eq!(sysfs.raw("panel"), "26");
+        assert_eq!(backlight.brightness().unwrap(), 10);
+        backlight.set_brightness(0).unwrap();
+        assert_eq!(sysfs.raw("panel"), "0");
+    }
+
+    #[test]
+    fn clamps_above_full_brightness() {
+        let sysfs = FakeSysfs::new("clamp", &[("panel", "0", "1000")]);
+        let backlight = SysfsBacklight::open(&sysfs.0, Some("panel")).unwrap();
+        backlight.set_brightness(150).unwrap();
+        assert_eq!(sysfs.raw("panel"), "1000");
+        assert_eq!(backlight.brightness().unwrap(), 100);
+    }
+
+    #[test]
+    fn picks_the_first_device() {
+        let sysfs = FakeSysfs::new("first", &[("b-panel", "5", "10"), ("a-panel", "3", "10")]);
+        let backlight = SysfsBacklight::open(&sysfs.0, None).unwrap();
+        assert_eq!(backlight.brightness().unwrap(), 30);
+    }
+
+    #[test]
+    fn rejects_broken_devices() {
+        let sysfs = FakeSysfs::new("broken", &[("dark", "0", "0"), ("garbage", "0", "lots")]);
+        assert!(SysfsBacklight::open(&sysfs.0, Some("dark")).is_err());
+        assert!(SysfsBacklight::open(&sysfs.0, Some("garbage")).is_err());
+        assert!(SysfsBacklight::open(&sysfs.0, Some("missing")).is_err());
+        let empty = FakeSysfs::new("empty", &[]);
+        fs::create_dir_all(&empty.0).unwrap();
+        assert!(SysfsBacklight::open(&empty.0, None).is_err());
+    }
+}
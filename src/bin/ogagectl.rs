@@ -0,0 +1,216 @@
+use props_rs::*;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+static OGAGE_CFG_FILE: &str = "/usr/local/etc/ogage.conf";
+
+static USAGE: &str = "Usage: ogagectl [--socket <path>] <command>
+
+Commands:
+    status
+    volume up|down|mute|unmute|<n>|+<n>|-<n>
+    brightness up|down|<n>|+<n>|-<n>
+    perf <profile>|cycle
+    wifi on|off
+    bluetooth on|off
+    inhibit [--what dim|suspend|idle] [--who <name>] [--reason <text>] -- <program> [<args>...]
+    inhibitors
+    uninhibit [<name>]
+    reload
+    subscribe
+    <action>, like screen_toggle or suspend";
+
+/// A connection to the control socket of ogage.
+struct Connection {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Connection {
+    fn open(path: &Path) -> Result<Connection, String> {
+        let stream = UnixStream::connect(path).map_err(|e| format!("can't connect to {}: {}", path.display(), e))?;
+        let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        Ok(Connection { stream, reader })
+    }
+
+    /// Sends a command and returns the reply of ogage, or the error it
+    /// answered with.
+    fn request(&mut self, command: &str) -> Result<String, String> {
+        writeln!(self.stream, "{}", command).map_err(|e| format!("can't send the command: {}", e))?;
+
+        let mut reply = String::new();
+        match self.reader.read_line(&mut reply) {
+            Ok(0) => return Err("ogage closed the connection".to_string()),
+            Ok(_) => (),
+            Err(e) => return Err(format!("can't read the reply: {}", e)),
+        }
+        let reply = reply.trim_end().to_string();
+        if reply.starts_with(r#"{"ok":true"#) {
+            Ok(reply)
+        } else {
+            Err(error_message(&reply))
+        }
+    }
+}
+
+fn main() {
+    match run() {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("ogagectl: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run() -> Result<i32, String> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut socket = None;
+    if args.first().map(String::as_str) == Some("--socket") {
+        if args.len() < 2 {
+            return Err("--socket needs a path".to_string());
+        }
+        socket = Some(PathBuf::from(args.remove(1)));
+        args.remove(0);
+    }
+
+    match args.first().map(String::as_str) {
+        None => {
+            eprintln!("{}", USAGE);
+            Ok(2)
+        }
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        Some("inhibit") => inhibit(&socket_path(socket)?, &args[1..]),
+        Some("subscribe") if args.len() == 1 => subscribe(&socket_path(socket)?),
+        Some(_) => {
+            let reply = Connection::open(&socket_path(socket)?)?.request(&args.join(" "))?;
+            // Replies with more than "ok" carry values worth printing
+            if reply != r#"{"ok":true}"# {
+                println!("{}", reply);
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// Holds an inhibitor while the program runs and exits like it did. The
+/// connection going away with ogagectl releases it, however it ends.
+fn inhibit(socket: &Path, args: &[String]) -> Result<i32, String> {
+    let mut what = "idle".to_string();
+    let mut who = None;
+    let mut reason = None;
+    let mut args = args.iter();
+    let program: Vec<&String> = loop {
+        match args.next().map(String::as_str) {
+            Some("--what") => what = args.next().ok_or("--what needs dim, suspend or idle")?.clone(),
+            Some("--who") => who = Some(args.next().ok_or("--who needs a name")?.clone()),
+            Some("--reason") => reason = Some(args.next().ok_or("--reason needs a text")?.clone()),
+            Some("--") => break args.collect(),
+            _ => return Err("usage: ogagectl inhibit [--what dim|suspend|idle] [--who <name>] [--reason <text>] -- <program> [<args>...]".to_string()),
+        }
+    };
+    let (program, program_args) = program.split_first().ok_or("inhibit needs a program to run")?;
+    // The name is one word, the reason the rest of the line
+    let who = who
+        .unwrap_or_else(|| Path::new(program.as_str()).file_name().map_or_else(|| program.to_string(), |name| name.to_string_lossy().into_owned()))
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_");
+    let reason = reason.unwrap_or_else(|| program.to_string()).replace('\n', " ");
+
+    let mut connection = Connection::open(socket)?;
+    connection.request(&format!("inhibit {} {} {}", what, who, reason))?;
+
+    let status = process::Command::new(program)
+        .args(program_args)
+        .status()
+        .map_err(|e| format!("can't run {}: {}", program, e))?;
+    drop(connection);
+
+    Ok(status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+}
+
+/// Prints the events, one JSON object per line, until ogage goes away.
+fn subscribe(socket: &Path) -> Result<i32, String> {
+    let mut connection = Connection::open(socket)?;
+    connection.request("subscribe")?;
+
+    let stdout = io::stdout();
+    for line in connection.reader.lines() {
+        let line = line.map_err(|e| format!("can't read the events: {}", e))?;
+        let mut stdout = stdout.lock();
+        // Flushed for every event, to be piped into scripts
+        if writeln!(stdout, "{}", line).and_then(|_| stdout.flush()).is_err() {
+            break;
+        }
+    }
+    Ok(0)
+}
+
+/// The socket given with `--socket`, the `control_socket` of ogage.conf or
+/// the first of the default locations which exists: ogage uses /run when
+/// it runs as root and the runtime directory of the user otherwise.
+fn socket_path(socket: Option<PathBuf>) -> Result<PathBuf, String> {
+    if let Some(socket) = socket {
+        return Ok(socket);
+    }
+    match configured_socket().as_deref() {
+        Some("disabled") => return Err(format!("the control socket is disabled in {}", OGAGE_CFG_FILE)),
+        Some(socket) if !socket.is_empty() => return Ok(PathBuf::from(socket)),
+        _ => (),
+    }
+
+    let mut candidates = vec![PathBuf::from("/run/ogage.sock")];
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        candidates.push(Path::new(&dir).join("ogage.sock"));
+    }
+    candidates.push(PathBuf::from(format!("/tmp/ogage-{}.sock", unsafe { libc::geteuid() })));
+    candidates
+        .into_iter()
+        .find(|candidate| candidate.exists())
+        .ok_or_else(|| "no control socket found, is ogage running?".to_string())
+}
+
+fn configured_socket() -> Option<String> {
+    let text = fs::read_to_string(OGAGE_CFG_FILE).ok()?;
+    let properties = to_map(parse(text.as_bytes()).ok()?);
+    properties.get("control_socket").map(|socket| socket.trim().to_string())
+}
+
+/// The `error` of a `{"ok":false,"error":"..."}` reply.
+fn error_message(reply: &str) -> String {
+    let start = match reply.find(r#""error":""#) {
+        Some(start) => start + r#""error":""#.len(),
+        None => return format!("unexpected reply: {}", reply),
+    };
+
+    let mut message = String::new();
+    let mut chars = reply[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => message.push('\n'),
+                Some('t') => message.push('\t'),
+                Some('r') => message.push('\r'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    message.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+                }
+                Some(c) => message.push(c),
+                None => break,
+            },
+            c => message.push(c),
+        }
+    }
+    message
+}
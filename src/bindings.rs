@@ -0,0 +1,190 @@
+use evdev::enums::EventCode;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::action::Action;
+use crate::profile::parse_key_code;
+
+/// Prefix of the `ogage.conf` keys that bind actions, e.g.
+/// `binding.vol_up=hotkey+BTN_NORTH`.
+pub static BINDING_PREFIX: &str = "binding.";
+
+/// When a binding fires once all its keys are down.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// As soon as the last key of the chord is pressed.
+    Press,
+    /// After the whole chord was held for the given time.
+    Hold(Duration),
+    /// When the chord is let go, unless another binding fired meanwhile.
+    Release,
+}
+
+/// A chord of buttons which triggers an action.
+#[derive(Clone)]
+pub struct Binding {
+    pub action: Action,
+    /// Keys of the chord, besides the hotkey.
+    pub keys: Vec<EventCode>,
+    /// Whether the hotkey has to be held; when false the binding only
+    /// fires while the hotkey is released.
+    pub with_hotkey: bool,
+    pub trigger: Trigger,
+}
+
+impl Binding {
+    /// Parses `[hotkey+]KEY_CODE[+KEY_CODE...] [hold <ms>|release]`.
+    pub fn parse(action: Action, value: &str) -> Result<Binding, String> {
+        let mut words = value.split_whitespace();
+        let chord = words.next().ok_or_else(|| format!("no key in '{}'", value))?;
+
+        let mut with_hotkey = false;
+        let mut keys = Vec::new();
+        for key in chord.split('+').map(|key| key.trim()) {
+            if key.eq_ignore_ascii_case("hotkey") {
+                with_hotkey = true;
+            } else {
+                let code = parse_key_code(key).ok_or_else(|| format!("unknown key code '{}'", key))?;
+                if !keys.contains(&code) {
+                    keys.push(code);
+                }
+            }
+        }
+        if keys.is_empty() {
+            return Err(format!("no key besides the hotkey in '{}'", value));
+        }
+
+        let trigger = match (words.next(), words.next()) {
+            (None, _) => Trigger::Press,
+            (Some("release"), None) => Trigger::Release,
+            (Some("hold"), Some(ms)) => {
+                let ms = ms.parse::<u64>().map_err(|_| format!("bad hold time '{}'", ms))?;
+                Trigger::Hold(Duration::from_millis(ms))
+            }
+            _ => return Err(format!("expected 'hold <ms>' or 'release' after the keys in '{}'", value)),
+        };
+        if words.next().is_some() {
+            return Err(format!("unexpected text after the trigger in '{}'", value));
+        }
+
+        Ok(Binding { action, keys, with_hotkey, trigger })
+    }
+
+    /// Whether both bindings react to exactly the same input.
+    pub fn same_input(&self, other: &Binding) -> bool {
+        self.with_hotkey == other.with_hotkey
+            && self.trigger == other.trigger
+            && self.keys.len() == other.keys.len()
+            && self.keys.iter().all(|key| other.keys.contains(key))
+    }
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}=", self.action)?;
+        if self.with_hotkey {
+            write!(f, "hotkey+")?;
+        }
+        let keys: Vec<String> = self.keys.iter().map(|key| key.to_string()).collect();
+        write!(f, "{}", keys.join("+"))?;
+        match self.trigger {
+            Trigger::Press => Ok(()),
+            Trigger::Hold(time) => write!(f, " hold {}", time.as_millis()),
+            Trigger::Release => write!(f, " release"),
+        }
+    }
+}
+
+/// Reads the `binding.<action>` keys of `properties`. `None` stands for an
+/// action the user unbound with `disabled` or an empty value. Invalid
+/// entries are added to `warnings` and skipped.
+pub fn parse_overrides(properties: &HashMap<String, String>, warnings: &mut Vec<String>) -> Vec<(Action, Option<Binding>)> {
+    let mut overrides = Vec::new();
+
+    for (key, value) in properties.iter() {
+        let name = match key.strip_prefix(BINDING_PREFIX) {
+            Some(name) => name,
+            None => continue,
+        };
+        let action = match Action::from_name(name) {
+            Some(action) => action,
+            None => {
+                warnings.push(format!("Ignoring {}: unknown action '{}'", key, name));
+                continue;
+            }
+        };
+
+        let value = value.trim();
+        if value.is_empty() || value == "disabled" {
+            overrides.push((action, None));
+            continue;
+        }
+
+        match Binding::parse(action, value) {
+            Ok(binding) => overrides.push((action, Some(binding))),
+            Err(e) => warnings.push(format!("Ignoring {}: {}", key, e)),
+        }
+    }
+
+    overrides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::enums::EV_KEY;
+
+    #[test]
+    fn parses_chords_and_triggers() {
+        let binding = Binding::parse(Action::VolUp, "hotkey+BTN_NORTH").unwrap();
+        assert!(binding.with_hotkey);
+        assert_eq!(binding.keys, vec![EventCode::EV_KEY(EV_KEY::BTN_NORTH)]);
+        assert!(binding.trigger == Trigger::Press);
+
+        let binding = Binding::parse(Action::Suspend, "BTN_START+BTN_SELECT+BTN_START hold 1500").unwrap();
+        assert!(!binding.with_hotkey);
+        assert_eq!(binding.keys.len(), 2);
+        assert!(binding.trigger == Trigger::Hold(Duration::from_millis(1500)));
+        assert_eq!(binding.to_string(), "suspend=BTN_START+BTN_SELECT hold 1500");
+
+        let binding = Binding::parse(Action::ScreenToggle, "HOTKEY+BTN_EAST release").unwrap();
+        assert!(binding.with_hotkey && binding.trigger == Trigger::Release);
+    }
+
+    #[test]
+    fn rejects_bad_bindings() {
+        let error = |value| Binding::parse(Action::VolUp, value).err().unwrap();
+        assert_eq!(error(""), "no key in ''");
+        assert_eq!(error("hotkey"), "no key besides the hotkey in 'hotkey'");
+        assert_eq!(error("hotkey+BTN_NOPE"), "unknown key code 'BTN_NOPE'");
+        assert_eq!(error("BTN_NORTH hold soon"), "bad hold time 'soon'");
+        assert_eq!(error("BTN_NORTH hold"), "expected 'hold <ms>' or 'release' after the keys in 'BTN_NORTH hold'");
+        assert_eq!(error("BTN_NORTH hold 500 ms"), "unexpected text after the trigger in 'BTN_NORTH hold 500 ms'");
+    }
+
+    #[test]
+    fn overrides_unbind_and_warn() {
+        let properties: HashMap<String, String> = [
+            ("binding.vol_up", "disabled"),
+            ("binding.vol_down", ""),
+            ("binding.nope", "BTN_NORTH"),
+            ("binding.suspend", "BTN_NOPE"),
+            ("wifi", "enabled"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        let mut warnings = Vec::new();
+        let overrides = parse_overrides(&properties, &mut warnings);
+        assert_eq!(overrides.len(), 2);
+        assert!(overrides.iter().all(|(_, binding)| binding.is_none()));
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            vec![
+                "Ignoring binding.nope: unknown action 'nope'",
+                "Ignoring binding.suspend: unknown key code 'BTN_NOPE'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use evdev::enums::EventCode;
+use mio::Token;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::action::Action;
+use crate::bindings::{Binding, Trigger};
+use crate::repeat::{Repeat, RepeatSettings};
+
+// How long a press chord which a longer chord starts with waits for the
+// rest of the longer one before firing
+static PREFIX_CHORD_WAIT: Duration = Duration::from_millis(300);
+
+/// A chord which is fully held down, waiting for its hold time or its
+/// release. Press chords wait here too when a longer chord starts with
+/// them.
+struct Armed {
+    index: usize,
+    since: Instant,
+    /// Set once the binding fired or another binding fired meanwhile.
+    done: bool,
+}
+
+/// Matches key presses against the bindings.
+///
+/// Pressed keys are tracked per input device, so a pad going away takes
+/// its keys with it, while chords are matched against the keys held on all
+/// devices together: the hotkey and the buttons don't always live on the
+/// same event node.
+pub struct ComboMatcher {
+    pressed: HashMap<Token, Vec<EventCode>>,
+    armed: Vec<Armed>,
+    /// `None` when software key repeat is disabled.
+    repeat_settings: Option<RepeatSettings>,
+    repeat: Option<Repeat>,
+}
+
+impl ComboMatcher {
+    pub fn new(repeat_settings: Option<RepeatSettings>) -> ComboMatcher {
+        ComboMatcher {
+            pressed: HashMap::new(),
+            armed: Vec::new(),
+            repeat_settings,
+            repeat: None,
+        }
+    }
+
+    /// Feeds a key event from the device of `token` and returns the actions
+    /// to run.
+    pub fn key_event(
+        &mut self,
+        token: Token,
+        code: &EventCode,
+        value: i32,
+        hotkey: &EventCode,
+        bindings: &[Binding],
+    ) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        match value {
+            1 => {
+                let keys = self.pressed.entry(token).or_default();
+                if !keys.contains(code) {
+                    keys.push(code.clone());
+                }
+
+                // The hotkey only modifies chords, pressing it completes none
+                let completed: Vec<usize> = if code == hotkey {
+                    Vec::new()
+                } else {
+                    bindings
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, binding)| binding.keys.contains(code) && self.chord_down(binding, hotkey))
+                        .map(|(index, _)| index)
+                        .collect()
+                };
+
+                // Hotkey+Select waits while Hotkey+Start+Select may still
+                // come
+                let pressed = most_keys(
+                    bindings,
+                    completed
+                        .iter()
+                        .copied()
+                        .filter(|index| bindings[*index].trigger == Trigger::Press && !is_prefix(bindings, *index)),
+                );
+                // Any other button stops the repeat
+                self.repeat = None;
+                if let Some(index) = pressed {
+                    actions.push(bindings[index].action);
+                    self.cancel_armed();
+                    if bindings[index].action.repeats() {
+                        self.repeat = self.repeat_settings.as_ref().map(|settings| Repeat::start(index, settings));
+                    }
+                }
+
+                for index in completed {
+                    let waits = bindings[index].trigger != Trigger::Press || is_prefix(bindings, index);
+                    if waits && !self.armed.iter().any(|armed| armed.index == index) {
+                        self.armed.push(Armed {
+                            index,
+                            since: Instant::now(),
+                            done: pressed.is_some(),
+                        });
+                    }
+                }
+            }
+            0 => {
+                if let Some(keys) = self.pressed.get_mut(&token) {
+                    keys.retain(|key| key != code);
+                }
+
+                let released: Vec<usize> = self
+                    .armed
+                    .iter()
+                    .filter(|armed| {
+                        !armed.done
+                            && matches!(bindings[armed.index].trigger, Trigger::Press | Trigger::Release)
+                            && !self.chord_down(&bindings[armed.index], hotkey)
+                    })
+                    .map(|armed| armed.index)
+                    .collect();
+                if let Some(index) = most_keys(bindings, released.into_iter()) {
+                    actions.push(bindings[index].action);
+                    self.cancel_armed();
+                }
+            }
+            // Kernel auto-repeat, repeats are timed by ourselves
+            _ => (),
+        }
+
+        self.disarm_released(hotkey, bindings);
+        actions
+    }
+
+    /// Takes the repeat settings of a new configuration. Armed chords and the
+    /// repeat point into the old bindings and are dropped, held keys stay.
+    pub fn reconfigure(&mut self, repeat_settings: Option<RepeatSettings>) {
+        self.repeat_settings = repeat_settings;
+        self.armed.clear();
+        self.repeat = None;
+    }
+
+    /// Forgets the keys held on a device which went away.
+    pub fn device_removed(&mut self, token: Token, hotkey: &EventCode, bindings: &[Binding]) {
+        self.pressed.remove(&token);
+        self.disarm_released(hotkey, bindings);
+    }
+
+    pub fn is_down(&self, code: &EventCode) -> bool {
+        self.pressed.values().any(|keys| keys.contains(code))
+    }
+
+    /// Time left until the next hold binding or repeat is due.
+    pub fn next_timeout(&self, bindings: &[Binding]) -> Option<Duration> {
+        let now = Instant::now();
+        self.armed
+            .iter()
+            .filter(|armed| !armed.done)
+            .filter_map(|armed| match bindings[armed.index].trigger {
+                Trigger::Hold(time) => Some((armed.since + time).saturating_duration_since(now)),
+                Trigger::Press => Some((armed.since + PREFIX_CHORD_WAIT).saturating_duration_since(now)),
+                Trigger::Release => None,
+            })
+            .chain(self.repeat.as_ref().map(|repeat| repeat.next_timeout()))
+            .min()
+    }
+
+    /// Fires the hold bindings and repeats which are due.
+    pub fn check(&mut self, bindings: &[Binding]) -> Vec<Action> {
+        if let (Some(repeat), Some(settings)) = (self.repeat.as_mut(), self.repeat_settings.as_ref()) {
+            if repeat.due(settings) {
+                return vec![bindings[repeat.index].action];
+            }
+        }
+
+        let now = Instant::now();
+        let due: Vec<usize> = self
+            .armed
+            .iter()
+            .filter(|armed| match bindings[armed.index].trigger {
+                Trigger::Hold(time) => !armed.done && armed.since + time <= now,
+                Trigger::Press => !armed.done && armed.since + PREFIX_CHORD_WAIT <= now,
+                Trigger::Release => false,
+            })
+            .map(|armed| armed.index)
+            .collect();
+
+        match most_keys(bindings, due.into_iter()) {
+            Some(index) => {
+                self.cancel_armed();
+                if bindings[index].trigger == Trigger::Press && bindings[index].action.repeats() {
+                    self.repeat = self.repeat_settings.as_ref().map(|settings| Repeat::start(index, settings));
+                }
+                vec![bindings[index].action]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn chord_down(&self, binding: &Binding, hotkey: &EventCode) -> bool {
+        binding.with_hotkey == self.is_down(hotkey) && binding.keys.iter().all(|key| self.is_down(key))
+    }
+
+    fn cancel_armed(&mut self) {
+        for armed in self.armed.iter_mut() {
+            armed.done = true;
+        }
+    }
+
+    fn disarm_released(&mut self, hotkey: &EventCode, bindings: &[Binding]) {
+        let repeating = self.repeat.as_ref().map(|repeat| repeat.index);
+        if repeating.is_some_and(|index| !self.chord_down(&bindings[index], hotkey)) {
+            self.repeat = None;
+        }
+
+        let armed = std::mem::take(&mut self.armed);
+        self.armed = armed
+            .into_iter()
+            .filter(|armed| self.chord_down(&bindings[armed.index], hotkey))
+            .collect();
+    }
+}
+
+/// Whether another chord, with the same use of the hotkey, holds all the
+/// keys of the binding at `index` and more.
+fn is_prefix(bindings: &[Binding], index: usize) -> bool {
+    let binding = &bindings[index];
+    bindings.iter().any(|other| {
+        other.with_hotkey == binding.with_hotkey
+            && other.keys.len() > binding.keys.len()
+            && binding.keys.iter().all(|key| other.keys.contains(key))
+    })
+}
+
+/// The most specific binding among `indexes`, so Hotkey+Start+Select
+/// wins over Hotkey+Select.
+fn most_keys(bindings: &[Binding], indexes: impl Iterator<Item = usize>) -> Option<usize> {
+    let mut best: Option<usize> = None;
+    for index in indexes {
+        if best.is_none_or(|best| bindings[index].keys.len() > bindings[best].keys.len()) {
+            best = Some(index);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::enums::EV_KEY;
+
+    fn key(key: EV_KEY) -> EventCode {
+        EventCode::EV_KEY(key)
+    }
+
+    fn bindings() -> Vec<Binding> {
+        vec![
+            Binding::parse(Action::WifiOn, "hotkey+BTN_SELECT").unwrap(),
+            Binding::parse(Action::Suspend, "hotkey+BTN_START+BTN_SELECT").unwrap(),
+            Binding::parse(Action::VolUp, "hotkey+BTN_NORTH").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn press_chord_fires_on_press() {
+        let (bindings, hotkey, pad) = (bindings(), key(EV_KEY::BTN_MODE), Token(0));
+        let mut combos = ComboMatcher::new(None);
+        assert!(combos.key_event(pad, &hotkey, 1, &hotkey, &bindings).is_empty());
+        assert_eq!(combos.key_event(pad, &key(EV_KEY::BTN_NORTH), 1, &hotkey, &bindings), vec![Action::VolUp]);
+    }
+
+    #[test]
+    fn longer_chord_wins_over_its_prefix() {
+        let (bindings, hotkey, pad) = (bindings(), key(EV_KEY::BTN_MODE), Token(0));
+        let mut combos = ComboMatcher::new(None);
+        combos.key_event(pad, &hotkey, 1, &hotkey, &bindings);
+        assert!(combos.key_event(pad, &key(EV_KEY::BTN_SELECT), 1, &hotkey, &bindings).is_empty());
+        assert_eq!(combos.key_event(pad, &key(EV_KEY::BTN_START), 1, &hotkey, &bindings), vec![Action::Suspend]);
+        assert!(combos.key_event(pad, &key(EV_KEY::BTN_SELECT), 0, &hotkey, &bindings).is_empty());
+        assert!(combos.key_event(pad, &key(EV_KEY::BTN_START), 0, &hotkey, &bindings).is_empty());
+    }
+
+    #[test]
+    fn prefix_chord_fires_on_release() {
+        let (bindings, hotkey, pad) = (bindings(), key(EV_KEY::BTN_MODE), Token(0));
+        let mut combos = ComboMatcher::new(None);
+        combos.key_event(pad, &hotkey, 1, &hotkey, &bindings);
+        assert!(combos.key_event(pad, &key(EV_KEY::BTN_SELECT), 1, &hotkey, &bindings).is_empty());
+        assert_eq!(combos.key_event(pad, &key(EV_KEY::BTN_SELECT), 0, &hotkey, &bindings), vec![Action::WifiOn]);
+    }
+
+    #[test]
+    fn prefix_chord_fires_after_waiting() {
+        let (bindings, hotkey, pad) = (bindings(), key(EV_KEY::BTN_MODE), Token(0));
+        let mut combos = ComboMatcher::new(None);
+        combos.key_event(pad, &hotkey, 1, &hotkey, &bindings);
+        combos.key_event(pad, &key(EV_KEY::BTN_SELECT), 1, &hotkey, &bindings);
+        assert!(combos.check(&bindings).is_empty());
+        assert!(combos.next_timeout(&bindings).is_some());
+        std::thread::sleep(PREFIX_CHORD_WAIT);
+        assert_eq!(combos.check(&bindings), vec![Action::WifiOn]);
+        assert!(combos.key_event(pad, &key(EV_KEY::BTN_SELECT), 0, &hotkey, &bindings).is_empty());
+    }
+}
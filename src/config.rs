@@ -0,0 +1,630 @@
+use props_rs::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::action::Action;
+use crate::bindings::{self, Binding, BINDING_PREFIX};
+use crate::control;
+use crate::devices::DeviceRule;
+use crate::error::Error;
+use crate::gesture::{self, Gesture, GestureKey, GestureTiming, GESTURE_PREFIX};
+use crate::inotify::{Inotify, InotifyEvent};
+use crate::perf::{self, PerfProfile, PERF_PREFIX};
+use crate::processes::ProcessRule;
+use crate::profile::PROFILES_DIR;
+use crate::repeat::RepeatSettings;
+
+pub static DEVICE_FILE: &str = "/opt/.retrooz/device";
+pub static OGAGE_CFG_FILE: &str = "/usr/local/etc/ogage.conf";
+pub static POWERKEY_CFG_FILE: &str = "/usr/local/etc/powerkey.conf";
+pub static AUTO_SUSPEND_CFG_FILE: &str = "/usr/local/etc/auto_suspend.conf";
+pub static AUTO_DIM_CFG_FILE: &str = "/usr/local/etc/auto_dim.conf";
+
+/// Every file the configuration is read from.
+static CONFIG_FILES: [&str; 5] = [
+    DEVICE_FILE,
+    OGAGE_CFG_FILE,
+    POWERKEY_CFG_FILE,
+    AUTO_SUSPEND_CFG_FILE,
+    AUTO_DIM_CFG_FILE,
+];
+
+static WATCH_MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+
+static MIN_POWERKEY_ELAPSED: Duration = Duration::from_secs(1);
+
+/// Everything ogage reads from its configuration files, checked and with
+/// defaults filled in.
+pub struct Config {
+    /// Overrides the detected device profile.
+    pub device: Option<String>,
+    pub powerkey: PowerkeyConfig,
+    pub auto_suspend: AutoSuspendConfig,
+    pub auto_dim: AutoDimConfig,
+    pub brightness: BrightnessConfig,
+    pub volume: VolumeConfig,
+    pub radios: RadiosConfig,
+    pub performance: PerformanceConfig,
+    pub power: PowerConfig,
+    pub input: InputConfig,
+    pub control: ControlConfig,
+    /// Unknown keys and bad values, which were left out.
+    pub warnings: Vec<String>,
+}
+
+/// `powerkey.conf`.
+pub struct PowerkeyConfig {
+    pub gestures: Vec<(Gesture, Action)>,
+    pub timing: GestureTiming,
+}
+
+/// `auto_suspend.conf`.
+pub struct AutoSuspendConfig {
+    pub enabled: bool,
+    pub timeout: Duration,
+    pub stay_awake_while_charging: bool,
+    /// Processes which keep the device from suspending while they run.
+    pub inhibit_processes: Vec<ProcessRule>,
+    /// Whether sound playing counts as activity.
+    pub audio_activity: bool,
+}
+
+/// `auto_dim.conf`.
+pub struct AutoDimConfig {
+    pub enabled: bool,
+    pub timeout: Duration,
+    /// In percent.
+    pub brightness: u32,
+    pub stay_awake_while_charging: bool,
+    /// Processes which keep the screen from dimming while they run.
+    pub inhibit_processes: Vec<ProcessRule>,
+    /// Whether sound playing counts as activity.
+    pub audio_activity: bool,
+}
+
+pub struct BrightnessConfig {
+    pub allowed: bool,
+    pub step: u32,
+    /// `brightnessctl`, a device of /sys/class/backlight or nothing to pick
+    /// one.
+    pub backend: Option<String>,
+    pub es_lock_file: String,
+}
+
+pub struct VolumeConfig {
+    pub allowed: bool,
+    pub step: u32,
+    /// `amixer` or nothing for the ALSA control interface.
+    pub backend: Option<String>,
+    pub card: u32,
+    pub control: String,
+}
+
+pub struct RadiosConfig {
+    pub allow_wifi: bool,
+    pub allow_bluetooth: bool,
+    /// `nmcli` or nothing for /dev/rfkill.
+    pub backend: Option<String>,
+}
+
+pub struct PerformanceConfig {
+    pub allowed: bool,
+    pub profiles: Vec<PerfProfile>,
+}
+
+pub struct PowerConfig {
+    pub allow_suspend: bool,
+    /// `logind`, `sysfs`, `sudo` or nothing to pick one.
+    pub backend: Option<String>,
+}
+
+pub struct InputConfig {
+    pub bindings: Vec<(Action, Option<Binding>)>,
+    pub gestures: Vec<GestureKey>,
+    pub key_repeat: Option<RepeatSettings>,
+    pub hotkey_grab: bool,
+    pub devices_include: Vec<DeviceRule>,
+    pub devices_exclude: Vec<DeviceRule>,
+}
+
+pub struct ControlConfig {
+    /// `None` when the control socket is disabled.
+    pub socket: Option<PathBuf>,
+    /// Group allowed to use the control socket on top of the owner.
+    pub group: Option<String>,
+    /// Bus of the D-Bus service, `None` when it is disabled.
+    #[cfg(feature = "dbus-service")]
+    pub dbus: Option<ServiceBus>,
+}
+
+#[cfg(feature = "dbus-service")]
+#[derive(Clone, Copy, PartialEq)]
+pub enum ServiceBus {
+    System,
+    Session,
+}
+
+/// Watches the configuration files and the installed device profiles.
+/// Their directories are watched rather than the files, which editors and
+/// frontends often replace or create.
+pub struct ConfigWatch {
+    inotify: Inotify,
+    /// Watch descriptor of each file.
+    watches: Vec<(i32, &'static Path)>,
+    /// Watch descriptor of the profiles directory, once it exists.
+    profiles: Option<i32>,
+}
+
+/// The key/value pairs of one file. Keys are marked as they are looked
+/// up, what is left unmarked is reported as unknown.
+struct Properties {
+    file: &'static str,
+    values: HashMap<String, String>,
+    used: HashSet<String>,
+    prefixes: Vec<&'static str>,
+    warnings: Vec<String>,
+}
+
+impl Config {
+    /// Reads the configuration files. Missing files and keys get their
+    /// defaults, problems end up in `warnings`.
+    pub fn load() -> Config {
+        let mut ogage = Properties::load("OGAGE PROPERTIES:", OGAGE_CFG_FILE);
+        let mut powerkey = Properties::load("POWERKEY_PROPERTIES:", POWERKEY_CFG_FILE);
+        let mut auto_suspend = Properties::load("AUTO_SUSPEND_PROPERTIES:", AUTO_SUSPEND_CFG_FILE);
+        let mut auto_dim = Properties::load("AUTO_DIM_PROPERTIES:", AUTO_DIM_CFG_FILE);
+        let mut warnings = Vec::new();
+
+        // comma separated process names or "cmdline:" patterns ('*'
+        // wildcards allowed)
+        let processes = |list: Option<String>| list.map(|list| ProcessRule::parse_list(&list)).unwrap_or_default();
+
+        let config = Config {
+            device: read_device(&mut warnings),
+            powerkey: PowerkeyConfig::parse(&mut powerkey),
+            auto_suspend: AutoSuspendConfig {
+                enabled: auto_suspend.switch("auto_suspend_time", false),
+                timeout: minutes(auto_suspend.number("auto_suspend_timeout", 5, 1..=1440, "a number of minutes from 1 to 1440")),
+                stay_awake_while_charging: auto_suspend.switch("auto_suspend_stay_awake_while_charging", false),
+                inhibit_processes: processes(auto_suspend.string("auto_suspend_inhibit_processes")),
+                audio_activity: auto_suspend.switch("auto_suspend_audio_activity", false),
+            },
+            auto_dim: AutoDimConfig {
+                enabled: auto_dim.switch("auto_dim_time", false),
+                timeout: minutes(auto_dim.number("auto_dim_timeout", 5, 1..=1440, "a number of minutes from 1 to 1440")),
+                brightness: auto_dim.number("auto_dim_brightness", 10, 0..=100, "a percentage from 0 to 100"),
+                stay_awake_while_charging: auto_dim.switch("auto_dim_stay_awake_while_charging", false),
+                inhibit_processes: processes(auto_dim.string("auto_dim_inhibit_processes")),
+                audio_activity: auto_dim.switch("auto_dim_audio_activity", false),
+            },
+            brightness: BrightnessConfig {
+                allowed: ogage.switch("brightness", true),
+                step: ogage.number("brightness_step", 1, 1..=100, "a percentage from 1 to 100"),
+                backend: ogage.string("backlight"),
+                es_lock_file: ogage
+                    .string("es_brigthness_lock_file")
+                    .unwrap_or_else(|| "/home/odroid/.emulationstation/brightness.lock".to_string()),
+            },
+            volume: VolumeConfig {
+                allowed: ogage.switch("volume", true),
+                step: ogage.number("volume_step", 1, 1..=100, "a percentage from 1 to 100"),
+                backend: ogage.string("mixer"),
+                card: ogage.number("mixer_card", 0, 0..=31, "a card number from 0 to 31"),
+                control: ogage.string("mixer_control").unwrap_or_else(|| "Playback".to_string()),
+            },
+            radios: RadiosConfig {
+                allow_wifi: ogage.switch("wifi", true),
+                allow_bluetooth: ogage.switch("bluetooth", true),
+                backend: ogage.string("radio"),
+            },
+            performance: PerformanceConfig {
+                allowed: ogage.switch("performance", true),
+                profiles: {
+                    ogage.used.insert("perf_profiles".to_string());
+                    ogage.prefixes.push(PERF_PREFIX);
+                    ogage.parse_with(perf::parse_profiles)
+                },
+            },
+            power: PowerConfig {
+                allow_suspend: ogage.switch("suspend", true),
+                backend: ogage.string("power"),
+            },
+            input: InputConfig::parse(&mut ogage),
+            control: ControlConfig {
+                socket: match ogage.string("control_socket").as_deref() {
+                    None | Some("") => Some(control::default_socket_path()),
+                    Some("disabled") => None,
+                    Some(path) => Some(PathBuf::from(path)),
+                },
+                group: ogage.string("control_socket_group").filter(|group| !group.is_empty()),
+                #[cfg(feature = "dbus-service")]
+                dbus: {
+                    // The system bus when running as root, like the socket
+                    let default = if unsafe { libc::geteuid() } == 0 { ServiceBus::System } else { ServiceBus::Session };
+                    match ogage.string("dbus_service").as_deref() {
+                        None => Some(default),
+                        Some("system") => Some(ServiceBus::System),
+                        Some("session") => Some(ServiceBus::Session),
+                        Some("disabled") => None,
+                        Some(value) => {
+                            ogage.bad_value("dbus_service", value, "system, session or disabled");
+                            Some(default)
+                        }
+                    }
+                },
+            },
+            warnings: Vec::new(),
+        };
+
+        for properties in [ogage, powerkey, auto_suspend, auto_dim] {
+            warnings.extend(properties.finish());
+        }
+        Config { warnings, ..config }
+    }
+}
+
+impl PowerkeyConfig {
+    /// Gesture keys, or the legacy `action` and `two_push_shutdown` keys
+    /// when there are none.
+    fn parse(properties: &mut Properties) -> PowerkeyConfig {
+        let mut gestures = properties.parse_with(|values, warnings| gesture::parse_actions(values, "", warnings));
+        for gesture in gesture::GESTURES.iter() {
+            properties.used.insert(gesture.name().to_string());
+        }
+
+        let mut timing = GestureTiming::default();
+        let two_push = properties.switch("two_push_shutdown", false);
+        // Seconds on top of the minimum second between both pushes
+        let max_interval = properties.number("max_interval_time", 1, 0..=60, "a number of seconds from 0 to 60");
+        let action = match properties.string("action").as_deref() {
+            None | Some("shutdown") => Some(Action::PowerOff),
+            Some("suspend") => Some(Action::Suspend),
+            Some("disabled") => None,
+            Some(value) => {
+                properties.bad_value("action", value, "shutdown, suspend or disabled");
+                Some(Action::PowerOff)
+            }
+        };
+
+        if gestures.is_empty() {
+            // two push at least in more than one second
+            timing.double_press_min = MIN_POWERKEY_ELAPSED;
+            timing.double_press_max = MIN_POWERKEY_ELAPSED + Duration::from_secs(max_interval);
+            gestures = match action {
+                Some(action) if two_push => vec![(Gesture::DoublePress, action)],
+                Some(Action::Suspend) => vec![(Gesture::Press, Action::Suspend)],
+                _ => Vec::new(),
+            };
+        }
+
+        let ms = "a number of milliseconds from 0 to 10000";
+        if let Some(x) = properties.get_number("long_press_time", 0..=10000, ms) {
+            timing.long_press = Duration::from_millis(x);
+        }
+        if let Some(x) = properties.get_number("double_press_min_interval", 0..=10000, ms) {
+            timing.double_press_min = Duration::from_millis(x);
+        }
+        if let Some(x) = properties.get_number("double_press_interval", 0..=10000, ms) {
+            timing.double_press_max = Duration::from_millis(x);
+        }
+        if timing.double_press_min > timing.double_press_max {
+            properties.warnings.push(format!(
+                "{}: double_press_min_interval is above double_press_interval, no double press can happen",
+                properties.file
+            ));
+        }
+
+        PowerkeyConfig { gestures, timing }
+    }
+}
+
+impl InputConfig {
+    fn parse(properties: &mut Properties) -> InputConfig {
+        properties.prefixes.push(BINDING_PREFIX);
+        properties.prefixes.push(GESTURE_PREFIX);
+        let bindings = properties.parse_with(bindings::parse_overrides);
+        let gestures = properties.parse_with(gesture::parse_keys);
+
+        // delay in milliseconds, rates in repeats per second; a max rate
+        // above the rate makes held buttons speed up
+        let key_repeat = if properties.switch("key_repeat", false) {
+            let delay = properties.number("key_repeat_delay", 400, 0..=5000, "a number of milliseconds from 0 to 5000");
+            let rate = properties.number("key_repeat_rate", 10, 1..=100, "a number of repeats per second from 1 to 100");
+            let max_rate = properties
+                .number("key_repeat_max_rate", rate, 1..=100, "a number of repeats per second from 1 to 100")
+                .max(rate);
+            Some(RepeatSettings {
+                delay: Duration::from_millis(delay),
+                interval: Duration::from_millis(1000 / rate),
+                min_interval: Duration::from_millis(1000 / max_rate),
+            })
+        } else {
+            None
+        };
+
+        // comma separated "vendor:product" IDs or device names ('*'
+        // wildcards allowed)
+        let rules = |list: Option<String>| list.map(|list| DeviceRule::parse_list(&list)).unwrap_or_default();
+
+        InputConfig {
+            bindings,
+            gestures,
+            key_repeat,
+            hotkey_grab: properties.switch("hotkey_grab", false),
+            devices_include: rules(properties.string("devices_include")),
+            devices_exclude: rules(properties.string("devices_exclude")),
+        }
+    }
+}
+
+impl Properties {
+    /// Reads a property file, an unreadable or invalid one counts as empty.
+    fn load(title: &str, file: &'static str) -> Properties {
+        let mut properties = Properties {
+            file,
+            values: HashMap::new(),
+            used: HashSet::new(),
+            prefixes: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        println!("\n{}", title);
+        if !Path::new(file).exists() {
+            return properties;
+        }
+
+        let parsed = fs::read_to_string(file)
+            .map_err(|e| Error::file(file, e))
+            .and_then(|lines| parse(lines.as_bytes()).map_err(|_| Error::ConfigSyntax { file: file.to_string() }));
+        match parsed {
+            Ok(parsed) => {
+                properties.values = to_map(parsed);
+
+                for (key, value) in properties.values.iter() {
+                    println!("\t{} / {}", key, value);
+                }
+                println!("\n");
+            }
+            Err(e) => properties.warnings.push(format!("{}, using defaults", e)),
+        }
+        properties
+    }
+
+    /// Runs one of the parsers of the other modules, which report their own
+    /// warnings.
+    fn parse_with<T>(&mut self, parser: impl FnOnce(&HashMap<String, String>, &mut Vec<String>) -> T) -> T {
+        let mut warnings = Vec::new();
+        let parsed = parser(&self.values, &mut warnings);
+        for warning in warnings {
+            self.warnings.push(format!("{}: {}", self.file, warning));
+        }
+        parsed
+    }
+
+    fn string(&mut self, key: &str) -> Option<String> {
+        self.used.insert(key.to_string());
+        self.values.get(key).map(|value| value.trim().to_string())
+    }
+
+    /// An `enabled`/`disabled` switch.
+    fn switch(&mut self, key: &str, default: bool) -> bool {
+        match self.string(key).as_deref() {
+            None => default,
+            Some("enabled") => true,
+            Some("disabled") => false,
+            Some(value) => {
+                self.bad_value(key, value, "enabled or disabled");
+                default
+            }
+        }
+    }
+
+    fn number<T>(&mut self, key: &str, default: T, range: RangeInclusive<T>, expected: &'static str) -> T
+    where
+        T: FromStr + PartialOrd + Display,
+    {
+        self.get_number(key, range, expected).unwrap_or(default)
+    }
+
+    /// The value of `key` when it is a number within `range`.
+    fn get_number<T>(&mut self, key: &str, range: RangeInclusive<T>, expected: &'static str) -> Option<T>
+    where
+        T: FromStr + PartialOrd + Display,
+    {
+        let value = self.string(key)?;
+        match value.parse::<T>() {
+            Ok(x) if range.contains(&x) => Some(x),
+            _ => {
+                self.bad_value(key, &value, expected);
+                None
+            }
+        }
+    }
+
+    fn bad_value(&mut self, key: &str, value: &str, expected: &'static str) {
+        let e = Error::ConfigValue {
+            file: self.file.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            expected,
+        };
+        self.warnings.push(format!("{}, using the default", e));
+    }
+
+    /// The warnings, with one for each key nobody asked for.
+    fn finish(mut self) -> Vec<String> {
+        let mut unknown: Vec<&String> = self
+            .values
+            .keys()
+            .filter(|key| !self.used.contains(*key) && !self.prefixes.iter().any(|prefix| key.starts_with(prefix)))
+            .collect();
+        unknown.sort();
+        for key in unknown {
+            self.warnings.push(format!("{}: unknown setting '{}', ignored", self.file, key));
+        }
+        self.warnings
+    }
+}
+
+impl ConfigWatch {
+    pub fn new() -> io::Result<ConfigWatch> {
+        let inotify = Inotify::new()?;
+        let mut watches = Vec::new();
+
+        for file in CONFIG_FILES.iter().map(Path::new) {
+            let dir = match file.parent() {
+                Some(dir) => dir,
+                None => continue,
+            };
+            match inotify.add_watch(dir, WATCH_MASK) {
+                Ok(wd) => watches.push((wd, file)),
+                // Configuration showing up in a new directory takes a SIGHUP
+                Err(e) => println!("Can't watch {} for changes: {}", dir.display(), e),
+            }
+        }
+
+        let mut watch = ConfigWatch {
+            inotify,
+            watches,
+            profiles: None,
+        };
+        watch.watch_profiles();
+        Ok(watch)
+    }
+
+    /// Starts watching the profiles directory if it wasn't yet. Most
+    /// installs have none, so it is looked for again on each reload.
+    pub fn watch_profiles(&mut self) {
+        if self.profiles.is_some() {
+            return;
+        }
+        match self.inotify.add_watch(Path::new(PROFILES_DIR), WATCH_MASK) {
+            Ok(wd) => self.profiles = Some(wd),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => println!("Can't watch {} for changes: {}", PROFILES_DIR, e),
+        }
+    }
+
+    /// Reads the pending events and tells whether one of the files changed.
+    pub fn changed(&mut self) -> bool {
+        let events = match self.inotify.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                println!("Can't read configuration changes: {}", e);
+                return false;
+            }
+        };
+
+        // The profiles directory went away, a new one is watched on reload
+        let profiles_gone = events
+            .iter()
+            .any(|event| Some(event.wd) == self.profiles && event.mask & libc::IN_IGNORED != 0);
+        if profiles_gone {
+            self.profiles = None;
+        }
+
+        let profile = |event: &InotifyEvent| {
+            Some(event.wd) == self.profiles
+                && event.name.as_ref().is_some_and(|name| Path::new(name).extension().is_some_and(|ext| ext == "conf"))
+        };
+        let config = |event: &InotifyEvent| {
+            self.watches.iter().any(|(wd, file)| *wd == event.wd && event.name.as_deref() == file.file_name())
+        };
+        profiles_gone || events.iter().any(|event| profile(event) || config(event))
+    }
+}
+
+impl AsRawFd for ConfigWatch {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
+
+fn read_device(warnings: &mut Vec<String>) -> Option<String> {
+    if !Path::new(DEVICE_FILE).exists() {
+        return None;
+    }
+    match fs::read_to_string(DEVICE_FILE) {
+        Ok(lines) => Some(lines.trim_end_matches(&['\r', '\n'][..]).to_string()).filter(|device| !device.is_empty()),
+        Err(e) => {
+            warnings.push(Error::file(DEVICE_FILE, e).to_string());
+            None
+        }
+    }
+}
+
+fn minutes(minutes: u64) -> Duration {
+    Duration::from_secs(minutes * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(values: &[(&str, &str)]) -> Properties {
+        Properties {
+            file: "test.conf",
+            values: values.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            used: HashSet::new(),
+            prefixes: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bad_values_fall_back_to_the_default() {
+        let mut properties = properties(&[("volume", "true"), ("volume_step", "200"), ("brightness_step", " 5 ")]);
+        assert!(properties.switch("volume", true));
+        assert_eq!(properties.number("volume_step", 1, 1..=100, "a percentage from 1 to 100"), 1);
+        assert_eq!(properties.number("brightness_step", 1, 1..=100, "a percentage from 1 to 100"), 5);
+        assert_eq!(
+            properties.finish(),
+            vec![
+                "test.conf: volume='true' should be enabled or disabled, using the default",
+                "test.conf: volume_step='200' should be a percentage from 1 to 100, using the default",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_settings_are_reported() {
+        let mut properties = properties(&[("wifi", "disabled"), ("wiif", "enabled"), ("binding.vol_up", "BTN_NORTH")]);
+        properties.prefixes.push(BINDING_PREFIX);
+        assert!(!properties.switch("wifi", true));
+        assert_eq!(properties.finish(), vec!["test.conf: unknown setting 'wiif', ignored"]);
+    }
+
+    #[test]
+    fn input_settings() {
+        let mut properties = properties(&[
+            ("binding.vol_up", "hotkey+BTN_NORTH"),
+            ("binding.vol_dwn", "BTN_SOUTH"),
+            ("gesture.BTN_MODE.long_press", "suspend"),
+            ("key_repeat", "enabled"),
+            ("key_repeat_rate", "20"),
+            ("devices_exclude", "1234:abcd, *Keyboard*"),
+        ]);
+        let input = InputConfig::parse(&mut properties);
+        assert_eq!(input.bindings.len(), 1);
+        assert_eq!(input.gestures.len(), 1);
+        let repeat = input.key_repeat.expect("key repeat enabled");
+        assert_eq!(repeat.delay, Duration::from_millis(400));
+        assert_eq!(repeat.interval, Duration::from_millis(50));
+        assert_eq!(repeat.min_interval, Duration::from_millis(50));
+        assert!(!input.hotkey_grab);
+        assert!(matches!(input.devices_exclude[0], DeviceRule::Id(0x1234, 0xabcd)));
+        assert_eq!(properties.finish(), vec!["test.conf: Ignoring binding.vol_dwn: unknown action 'vol_dwn'"]);
+    }
+
+    #[test]
+    fn key_repeat_is_off_by_default() {
+        assert!(InputConfig::parse(&mut properties(&[])).key_repeat.is_none());
+    }
+}
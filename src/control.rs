@@ -0,0 +1,504 @@
+use mio::net::{UnixListener, UnixStream};
+use mio::{Interest, Registry, Token};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::action::Action;
+use crate::error::Error;
+use crate::idle::InhibitKind;
+use crate::json::Json;
+
+/// Longest command line accepted, clients sending more are dropped.
+const MAX_LINE: usize = 4096;
+
+/// What a control client asks for, one command per line.
+pub enum Command {
+    Action(Action),
+    Volume(Level),
+    Mute(bool),
+    Brightness(Level),
+    Perf(String),
+    Status,
+    Inhibit {
+        kind: InhibitKind,
+        name: String,
+        reason: String,
+    },
+    Uninhibit(Option<String>),
+    Inhibitors,
+    Reload,
+    Subscribe,
+}
+
+/// A level in percent, or a change of it.
+#[derive(Clone, Copy)]
+pub enum Level {
+    Set(u32),
+    Change(i32),
+}
+
+/// The control socket and its clients. Clients may only connect when the
+/// socket permissions let them, and their credentials are checked again on
+/// accept: root, the user ogage runs as and, with a group, its members.
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+    group: Option<libc::gid_t>,
+    listener_token: Token,
+    clients: HashMap<Token, Client>,
+    next_token: usize,
+}
+
+struct Client {
+    stream: UnixStream,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    /// Closing once the pending replies are written.
+    closing: bool,
+}
+
+impl Command {
+    /// Parses `<action>`, `volume up|down|mute|unmute|<n>|+<n>|-<n>`,
+    /// `brightness up|down|<n>|+<n>|-<n>`, `perf <profile>|cycle`,
+    /// `wifi on|off`, `bluetooth on|off`, `status`,
+    /// `inhibit dim|suspend|idle <name> [<reason>]`, `uninhibit [<name>]`,
+    /// `inhibitors`, `reload` or `subscribe`.
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let verb = words.next().ok_or_else(|| "empty command".to_string())?;
+        let rest: Vec<&str> = words.collect();
+        let argument = |expected: &str| match rest.as_slice() {
+            [word] => Ok(*word),
+            _ => Err(format!("usage: {} {}", verb, expected)),
+        };
+
+        let command = match verb {
+            "volume" => match argument("up|down|mute|unmute|<n>|+<n>|-<n>")? {
+                "up" => Command::Action(Action::VolUp),
+                "down" => Command::Action(Action::VolDown),
+                "mute" => Command::Mute(true),
+                "unmute" => Command::Mute(false),
+                level => Command::Volume(Level::parse(level)?),
+            },
+            "brightness" => match argument("up|down|<n>|+<n>|-<n>")? {
+                "up" => Command::Action(Action::BrightUp),
+                "down" => Command::Action(Action::BrightDown),
+                level => Command::Brightness(Level::parse(level)?),
+            },
+            "perf" => match argument("<profile>|cycle")? {
+                "cycle" => Command::Action(Action::PerfCycle),
+                name => Command::Perf(name.to_string()),
+            },
+            "wifi" => match argument("on|off")? {
+                "on" => Command::Action(Action::WifiOn),
+                "off" => Command::Action(Action::WifiOff),
+                _ => return Err("usage: wifi on|off".to_string()),
+            },
+            "bluetooth" => match argument("on|off")? {
+                "on" => Command::Action(Action::BluetoothOn),
+                "off" => Command::Action(Action::BluetoothOff),
+                _ => return Err("usage: bluetooth on|off".to_string()),
+            },
+            "inhibit" => match (rest.first().and_then(|kind| InhibitKind::from_name(kind)), rest.get(1)) {
+                (Some(kind), Some(name)) => Command::Inhibit {
+                    kind,
+                    name: name.to_string(),
+                    reason: rest[2..].join(" "),
+                },
+                _ => return Err("usage: inhibit dim|suspend|idle <name> [<reason>]".to_string()),
+            },
+            "uninhibit" => match rest.as_slice() {
+                [] => Command::Uninhibit(None),
+                [name] => Command::Uninhibit(Some(name.to_string())),
+                _ => return Err("usage: uninhibit [<name>]".to_string()),
+            },
+            "status" | "inhibitors" | "reload" | "subscribe" if !rest.is_empty() => {
+                return Err(format!("{} takes no argument", verb));
+            }
+            "status" => Command::Status,
+            "inhibitors" => Command::Inhibitors,
+            "reload" => Command::Reload,
+            "subscribe" => Command::Subscribe,
+            name => match Action::from_name(name) {
+                Some(action) if rest.is_empty() => Command::Action(action),
+                Some(_) => return Err(format!("{} takes no argument", verb)),
+                None => return Err(format!("unknown command '{}'", name)),
+            },
+        };
+        Ok(command)
+    }
+}
+
+impl Level {
+    fn parse(level: &str) -> Result<Level, String> {
+        let bad = || format!("bad level '{}', expected 0 to 100 or a change like +5", level);
+        if level.starts_with('+') || level.starts_with('-') {
+            return level.parse::<i32>().map(Level::Change).map_err(|_| bad());
+        }
+        match level.parse::<u32>() {
+            Ok(percent) if percent <= 100 => Ok(Level::Set(percent)),
+            _ => Err(bad()),
+        }
+    }
+
+    /// The new level, `current` is only read for a change.
+    pub fn apply(self, current: impl FnOnce() -> Result<u32, Error>) -> Result<u32, Error> {
+        match self {
+            Level::Set(percent) => Ok(percent),
+            Level::Change(change) => Ok((i64::from(current()?) + i64::from(change)).clamp(0, 100) as u32),
+        }
+    }
+}
+
+impl ControlServer {
+    /// Listens on `path`, replacing a socket left behind by an ogage which
+    /// is gone. The socket is only accessible to the user ogage runs as,
+    /// and to the members of `group` when one is given.
+    pub fn bind(registry: &Registry, listener_token: Token, first_client_token: Token, path: &Path, group: Option<&str>) -> io::Result<ControlServer> {
+        let group = group.map(group_id).transpose()?;
+
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and isn't a socket", path.display())));
+            }
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use, is ogage running already?", path.display())));
+            }
+            fs::remove_file(path)?;
+        }
+
+        // Nobody gets to connect before the permissions are right
+        let umask = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(path);
+        unsafe {
+            libc::umask(umask);
+        }
+        // Dropping the server removes the socket again if the rest fails
+        let mut server = ControlServer {
+            listener: listener?,
+            path: path.to_path_buf(),
+            group,
+            listener_token,
+            clients: HashMap::new(),
+            next_token: first_client_token.0,
+        };
+        if let Some(gid) = group {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            if unsafe { libc::chown(c_path.as_ptr(), u32::MAX, gid) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+        }
+
+        registry.register(&mut server.listener, listener_token, Interest::READABLE)?;
+        Ok(server)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the event of `token` is for the control socket or a client.
+    pub fn owns(&self, token: Token) -> bool {
+        token == self.listener_token || self.clients.contains_key(&token)
+    }
+
+    pub fn is_listener(&self, token: Token) -> bool {
+        token == self.listener_token
+    }
+
+    /// Accepts the pending connections.
+    pub fn accept(&mut self, registry: &Registry) {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    println!("Can't accept control connection: {}", e);
+                    return;
+                }
+            };
+
+            match peer_credentials(&stream) {
+                Ok(cred) if self.allowed(&cred) => (),
+                Ok(cred) => {
+                    println!("Refused control connection of uid {} (pid {})", cred.uid, cred.pid);
+                    continue;
+                }
+                Err(e) => {
+                    println!("Refused control connection: {}", e);
+                    continue;
+                }
+            }
+
+            let token = Token(self.next_token);
+            if let Err(e) = registry.register(&mut stream, token, Interest::READABLE) {
+                println!("Can't watch control connection: {}", e);
+                continue;
+            }
+            self.next_token += 1;
+            self.clients.insert(
+                token,
+                Client {
+                    stream,
+                    input: Vec::new(),
+                    output: Vec::new(),
+                    closing: false,
+                },
+            );
+        }
+    }
+
+    /// Reads what the client sent and returns the complete lines. `None`
+    /// means the client is gone or misbehaved and has to be removed.
+    pub fn read_lines(&mut self, token: Token) -> Option<Vec<String>> {
+        let client = self.clients.get_mut(&token)?;
+        let mut buffer = [0u8; 1024];
+
+        loop {
+            match client.stream.read(&mut buffer) {
+                Ok(0) => {
+                    // Still answers what came before the end of the input
+                    client.closing = true;
+                    break;
+                }
+                Ok(len) => client.input.extend_from_slice(&buffer[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(end) = client.input.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = client.input.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        if client.input.len() > MAX_LINE {
+            return None;
+        }
+        Some(lines.into_iter().filter(|line| !line.is_empty()).collect())
+    }
+
+    /// Queues a reply line for the client and writes what it can.
+    pub fn send(&mut self, registry: &Registry, token: Token, reply: &Json) {
+        if let Some(client) = self.clients.get_mut(&token) {
+            client.output.extend_from_slice(format!("{}\n", reply).as_bytes());
+        }
+        self.flush(registry, token);
+    }
+
+    /// Writes the pending replies of the client. Clients which are done are
+    /// removed once everything is written; returns false then.
+    pub fn flush(&mut self, registry: &Registry, token: Token) -> bool {
+        let client = match self.clients.get_mut(&token) {
+            Some(client) => client,
+            None => return false,
+        };
+
+        while !client.output.is_empty() {
+            match client.stream.write(&client.output) {
+                Ok(len) => {
+                    client.output.drain(..len);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.remove(registry, token);
+                    return false;
+                }
+            }
+        }
+
+        if client.output.is_empty() && client.closing {
+            self.remove(registry, token);
+            return false;
+        }
+        let interest = if client.output.is_empty() { Interest::READABLE } else { Interest::READABLE | Interest::WRITABLE };
+        if registry.reregister(&mut client.stream, token, interest).is_err() {
+            self.remove(registry, token);
+            return false;
+        }
+        true
+    }
+
+    /// Stops handling the client and returns its connection, with the
+    /// replies not written yet.
+    pub fn detach(&mut self, registry: &Registry, token: Token) -> Option<(UnixStream, Vec<u8>)> {
+        let mut client = self.clients.remove(&token)?;
+        let _ = registry.deregister(&mut client.stream);
+        Some((client.stream, client.output))
+    }
+
+    pub fn remove(&mut self, registry: &Registry, token: Token) {
+        if let Some(mut client) = self.clients.remove(&token) {
+            let _ = registry.deregister(&mut client.stream);
+        }
+    }
+
+    /// Closes the socket and every connection.
+    pub fn close(mut self, registry: &Registry) -> Vec<Token> {
+        let tokens: Vec<Token> = self.clients.keys().copied().collect();
+        for token in tokens.iter() {
+            self.remove(registry, *token);
+        }
+        let _ = registry.deregister(&mut self.listener);
+        tokens
+    }
+
+    fn allowed(&self, cred: &libc::ucred) -> bool {
+        if cred.uid == 0 || cred.uid == unsafe { libc::geteuid() } {
+            return true;
+        }
+        match self.group {
+            Some(gid) => cred.gid == gid || supplementary_groups(cred.pid).contains(&gid),
+            None => false,
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The reply line to a command: `{"ok":true,...}` with the fields of the
+/// result, or `{"ok":false,"error":"..."}`.
+pub fn reply(result: Result<Vec<(&str, Json)>, String>) -> Json {
+    match result {
+        Ok(fields) => Json::object(std::iter::once(("ok", Json::Bool(true))).chain(fields).collect()),
+        Err(e) => Json::object(vec![("ok", Json::Bool(false)), ("error", Json::String(e))]),
+    }
+}
+
+/// Where the control socket goes without a `control_socket` setting:
+/// `/run` for root, the user's runtime directory otherwise.
+pub fn default_socket_path() -> PathBuf {
+    if unsafe { libc::geteuid() } == 0 {
+        return PathBuf::from("/run/ogage.sock");
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("ogage.sock"),
+        None => PathBuf::from(format!("/tmp/ogage-{}.sock", unsafe { libc::geteuid() })),
+    }
+}
+
+fn peer_credentials(stream: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred)
+}
+
+fn supplementary_groups(pid: libc::pid_t) -> Vec<libc::gid_t> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| groups.split_whitespace().filter_map(|gid| gid.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+fn group_id(name: &str) -> io::Result<libc::gid_t> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c_name = CString::new(name)?;
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if group.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no group '{}'", name)));
+    }
+    Ok(unsafe { (*group).gr_gid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    fn error(line: &str) -> String {
+        Command::parse(line).err().unwrap()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert!(matches!(Command::parse(" vol_up "), Ok(Command::Action(Action::VolUp))));
+        assert!(matches!(Command::parse("volume down"), Ok(Command::Action(Action::VolDown))));
+        assert!(matches!(Command::parse("volume mute"), Ok(Command::Mute(true))));
+        assert!(matches!(Command::parse("volume 40"), Ok(Command::Volume(Level::Set(40)))));
+        assert!(matches!(Command::parse("brightness -15"), Ok(Command::Brightness(Level::Change(-15)))));
+        assert!(matches!(Command::parse("perf cycle"), Ok(Command::Action(Action::PerfCycle))));
+        assert!(matches!(Command::parse("perf powersave"), Ok(Command::Perf(name)) if name == "powersave"));
+        assert!(matches!(Command::parse("wifi off"), Ok(Command::Action(Action::WifiOff))));
+        assert!(matches!(Command::parse("uninhibit"), Ok(Command::Uninhibit(None))));
+        assert!(matches!(Command::parse("status"), Ok(Command::Status)));
+        assert!(matches!(
+            Command::parse("inhibit suspend player playing a long video"),
+            Ok(Command::Inhibit { kind: InhibitKind::Suspend, name, reason }) if name == "player" && reason == "playing a long video"
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert_eq!(error("  "), "empty command");
+        assert_eq!(error("explode"), "unknown command 'explode'");
+        assert_eq!(error("vol_up now"), "vol_up takes no argument");
+        assert_eq!(error("status please"), "status takes no argument");
+        assert_eq!(error("volume"), "usage: volume up|down|mute|unmute|<n>|+<n>|-<n>");
+        assert_eq!(error("volume 101"), "bad level '101', expected 0 to 100 or a change like +5");
+        assert_eq!(error("brightness +x"), "bad level '+x', expected 0 to 100 or a change like +5");
+        assert_eq!(error("wifi maybe"), "usage: wifi on|off");
+        assert_eq!(error("inhibit sleep player"), "usage: inhibit dim|suspend|idle <name> [<reason>]");
+        assert_eq!(error("inhibit dim"), "usage: inhibit dim|suspend|idle <name> [<reason>]");
+        assert_eq!(error("uninhibit a b"), "usage: uninhibit [<name>]");
+    }
+
+    #[test]
+    fn levels_stay_within_bounds() {
+        assert_eq!(Level::Set(30).apply(|| unreachable!()).unwrap(), 30);
+        assert_eq!(Level::Change(15).apply(|| Ok(90)).unwrap(), 100);
+        assert_eq!(Level::Change(-15).apply(|| Ok(10)).unwrap(), 0);
+        assert_eq!(Level::Change(-5).apply(|| Ok(50)).unwrap(), 45);
+    }
+
+    #[test]
+    fn binds_and_removes_the_socket() {
+        let poll = mio::Poll::new().unwrap();
+        let path = std::env::temp_dir().join(format!("ogage-control-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let server = ControlServer::bind(poll.registry(), Token(1), Token(100), &path, Some("0")).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+        assert_eq!(metadata.gid(), 0);
+        assert_eq!(
+            ControlServer::bind(poll.registry(), Token(2), Token(200), &path, None).err().unwrap().kind(),
+            io::ErrorKind::AddrInUse
+        );
+        drop(server);
+        assert!(!path.exists());
+
+        assert!(ControlServer::bind(poll.registry(), Token(1), Token(100), &path, Some("no-such-group")).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,645 @@
+use dbus::arg::{OwnedFd, PropMap, RefArg, Variant};
+use dbus::channel::{BusType, Channel};
+use dbus::message::MessageType;
+use dbus::{Message, MethodErr, Path};
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::action::Action;
+use crate::config::ServiceBus;
+use crate::control::{Command, Level};
+use crate::error::Error;
+use crate::event;
+use crate::idle::{IdleTimer, InhibitKind};
+use crate::json::Json;
+use crate::{battery_status, execute, get_brightness, get_volume, settings, SCREEN_OFF_BRIGHTNESS};
+
+pub static SERVICE_NAME: &str = "org.ogage.Daemon";
+static OBJECT_PATH: &str = "/org/ogage/Daemon";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+static INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+
+static INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.ogage.Daemon">
+    <method name="SetVolume">
+      <arg name="percent" type="u" direction="in"/>
+      <arg name="volume" type="u" direction="out"/>
+    </method>
+    <method name="SetBrightness">
+      <arg name="percent" type="u" direction="in"/>
+      <arg name="brightness" type="u" direction="out"/>
+    </method>
+    <method name="Suspend"/>
+    <method name="SetPerformanceProfile">
+      <arg name="profile" type="s" direction="in"/>
+    </method>
+    <method name="RunAction">
+      <arg name="action" type="s" direction="in"/>
+    </method>
+    <method name="Inhibit">
+      <arg name="what" type="s" direction="in"/>
+      <arg name="who" type="s" direction="in"/>
+      <arg name="why" type="s" direction="in"/>
+      <arg name="fd" type="h" direction="out"/>
+    </method>
+    <method name="ListInhibitors">
+      <arg name="inhibitors" type="a(ssst)" direction="out"/>
+    </method>
+    <property name="Volume" type="u" access="read"/>
+    <property name="Brightness" type="u" access="read"/>
+    <property name="PerformanceProfile" type="s" access="read"/>
+    <property name="Dimmed" type="b" access="read"/>
+    <property name="ScreenOff" type="b" access="read"/>
+    <property name="BatteryStatus" type="s" access="read">
+      <annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" value="false"/>
+    </property>
+    <signal name="Event">
+      <arg name="name" type="s"/>
+      <arg name="event" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed_properties" type="a{sv}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// `org.ogage.Daemon` on the system or session bus: the commands of the
+/// control socket as methods, the levels as properties and the events as
+/// signals. The bus policy decides who may call it.
+pub struct DbusService {
+    channel: Channel,
+    token: Token,
+    /// Read ends of the pipes handed out by `Inhibit`, the inhibitor lasts
+    /// until the client closes the write end, or dies.
+    inhibit_pipes: HashMap<Token, RawFd>,
+    next_pipe_token: usize,
+    /// The properties as last told, kept up to date from the events, to
+    /// signal what changed.
+    properties: Vec<(&'static str, Value)>,
+}
+
+#[derive(Clone, PartialEq)]
+enum Value {
+    U32(u32),
+    String(String),
+    Bool(bool),
+}
+
+impl DbusService {
+    /// Connects to the bus and takes the service name. The events are
+    /// queued from now on, for `publish`.
+    pub fn connect(
+        registry: &Registry,
+        token: Token,
+        first_pipe_token: Token,
+        bus: ServiceBus,
+        idle: &IdleTimer,
+    ) -> Result<DbusService, dbus::Error> {
+        let mut channel = Channel::get_private(match bus {
+            ServiceBus::System => BusType::System,
+            ServiceBus::Session => BusType::Session,
+        })?;
+
+        // Not queued behind another owner, a second ogage has to fail
+        let request = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "RequestName")
+            .map_err(|e| dbus::Error::new_failed(&e))?
+            .append2(SERVICE_NAME, 4u32);
+        let reply = channel.send_with_reply_and_block(request, Duration::from_secs(5))?;
+        let result: u32 = reply.read1().map_err(|e| dbus::Error::new_failed(&e.to_string()))?;
+        if result != 1 {
+            return Err(dbus::Error::new_failed(&format!("{} is owned by another program", SERVICE_NAME)));
+        }
+
+        channel.set_watch_enabled(true);
+        let fd = channel.watch().fd;
+        registry
+            .register(&mut SourceFd(&fd), token, Interest::READABLE)
+            .map_err(|e| dbus::Error::new_failed(&e.to_string()))?;
+
+        event::queue_events(true);
+        Ok(DbusService {
+            channel,
+            token,
+            inhibit_pipes: HashMap::new(),
+            next_pipe_token: first_pipe_token.0,
+            properties: properties(idle),
+        })
+    }
+
+    /// Answers the pending method calls. Returns false once the bus went
+    /// away.
+    pub fn dispatch(&mut self, registry: &Registry, idle: &mut IdleTimer, reload_due: &mut Option<Instant>) -> bool {
+        loop {
+            if self.channel.read_write(Some(Duration::ZERO)).is_err() {
+                return false;
+            }
+            while let Some(message) = self.channel.pop_message() {
+                if message.msg_type() != MessageType::MethodCall {
+                    continue;
+                }
+                let reply = self.method_call(&message, registry, idle, reload_due).unwrap_or_else(|e| e.to_message(&message));
+                if !message.get_no_reply() {
+                    let _ = self.channel.send(reply);
+                }
+            }
+            // Reading is edge triggered, what libdbus left unread is
+            // read now or never
+            if !self.readable() {
+                break;
+            }
+        }
+        self.channel.flush();
+        self.channel.is_connected()
+    }
+
+    /// Signals the events since the last call, and the properties they
+    /// changed. The levels are taken from the events rather than read
+    /// again.
+    pub fn publish(&mut self, idle: &IdleTimer) {
+        let events = event::take_queued();
+        if events.is_empty() {
+            return;
+        }
+
+        let path: Path = OBJECT_PATH.into();
+        let mut properties = self.properties.clone();
+        for (name, event) in events {
+            if let Some((property, value)) = changed_property(&name, &event) {
+                properties.retain(|(other, _)| *other != property);
+                properties.extend(value.map(|value| (property, value)));
+            }
+            let signal = Message::signal(&path, &SERVICE_NAME.into(), &"Event".into()).append2(name, event.to_string());
+            let _ = self.channel.send(signal);
+        }
+        properties.retain(|(name, _)| *name != "Dimmed" && *name != "ScreenOff");
+        properties.extend(state_properties(idle));
+        let changed: PropMap = properties
+            .iter()
+            .filter(|property| !self.properties.contains(property))
+            .map(|(name, value)| (name.to_string(), value.variant()))
+            .collect();
+        // Properties which can't be read anymore
+        let invalidated: Vec<String> = self
+            .properties
+            .iter()
+            .filter(|(name, _)| !properties.iter().any(|(other, _)| other == name))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        if !changed.is_empty() || !invalidated.is_empty() {
+            let signal = Message::signal(&path, &PROPERTIES_INTERFACE.into(), &"PropertiesChanged".into())
+                .append3(SERVICE_NAME, changed, invalidated);
+            let _ = self.channel.send(signal);
+        }
+        self.properties = properties;
+        self.channel.flush();
+    }
+
+    /// Whether the event of `token` is for an inhibitor pipe.
+    pub fn owns(&self, token: Token) -> bool {
+        self.inhibit_pipes.contains_key(&token)
+    }
+
+    /// Releases the inhibitor once its pipe is closed. Whatever the client
+    /// writes into it is ignored.
+    pub fn pipe_event(&mut self, registry: &Registry, token: Token, idle: &mut IdleTimer) {
+        let fd = match self.inhibit_pipes.get(&token) {
+            Some(fd) => *fd,
+            None => return,
+        };
+        let mut buffer = [0u8; 64];
+        loop {
+            match unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } {
+                0 => break,
+                len if len > 0 => continue,
+                _ if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock => return,
+                _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                _ => break,
+            }
+        }
+        self.close_pipe(registry, token);
+        idle.release(token, None);
+    }
+
+    /// Leaves the bus, and stops queueing events. The inhibitors taken
+    /// over D-Bus go away with it.
+    pub fn close(mut self, registry: &Registry, idle: &mut IdleTimer) {
+        let tokens: Vec<Token> = self.inhibit_pipes.keys().copied().collect();
+        for token in tokens {
+            self.close_pipe(registry, token);
+            idle.release(token, None);
+        }
+        let _ = registry.deregister(&mut SourceFd(&self.channel.watch().fd));
+        event::queue_events(false);
+    }
+
+    /// Takes an inhibitor held by the write end of a pipe, which is
+    /// returned for the client.
+    fn inhibit(&mut self, registry: &Registry, idle: &mut IdleTimer, kind: InhibitKind, name: &str, reason: &str) -> io::Result<OwnedFd> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read_end, write_end) = unsafe { (fds[0], OwnedFd::new(fds[1])) };
+
+        let token = Token(self.next_pipe_token);
+        if let Err(e) = registry.register(&mut SourceFd(&read_end), token, Interest::READABLE) {
+            unsafe {
+                libc::close(read_end);
+            }
+            return Err(e);
+        }
+        self.next_pipe_token += 1;
+        self.inhibit_pipes.insert(token, read_end);
+        idle.inhibit(token, kind, name.to_string(), reason.to_string());
+        Ok(write_end)
+    }
+
+    fn close_pipe(&mut self, registry: &Registry, token: Token) {
+        if let Some(fd) = self.inhibit_pipes.remove(&token) {
+            let _ = registry.deregister(&mut SourceFd(&fd));
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+
+    fn method_call(
+        &mut self,
+        message: &Message,
+        registry: &Registry,
+        idle: &mut IdleTimer,
+        reload_due: &mut Option<Instant>,
+    ) -> Result<Message, MethodErr> {
+        let path = message.path().ok_or_else(MethodErr::no_arg)?;
+        if &*path != OBJECT_PATH {
+            return Err(MethodErr::no_path(&path));
+        }
+        let member = message.member().ok_or_else(MethodErr::no_arg)?;
+        let interface = message.interface();
+
+        match (interface.as_deref(), &*member) {
+            (Some(i), "Introspect") if i == INTROSPECTABLE_INTERFACE => Ok(message.method_return().append1(INTROSPECTION)),
+            (Some(i), "Get") if i == PROPERTIES_INTERFACE => {
+                let (interface, name): (&str, &str) = message.read2()?;
+                check_interface(interface)?;
+                let value = properties(idle)
+                    .into_iter()
+                    .find(|(property, _)| *property == name)
+                    .map(|(_, value)| value)
+                    .ok_or_else(|| MethodErr::no_property(name))?;
+                Ok(message.method_return().append1(value.variant()))
+            }
+            (Some(i), "GetAll") if i == PROPERTIES_INTERFACE => {
+                check_interface(message.read1()?)?;
+                let all: PropMap = properties(idle).into_iter().map(|(name, value)| (name.to_string(), value.variant())).collect();
+                Ok(message.method_return().append1(all))
+            }
+            (Some(i), "Set") if i == PROPERTIES_INTERFACE => {
+                let (_, name): (&str, &str) = message.read2()?;
+                Err(MethodErr::ro_property(name))
+            }
+            (None, member) => self.daemon_call(message, member, registry, idle, reload_due),
+            (Some(i), member) if i == SERVICE_NAME => self.daemon_call(message, member, registry, idle, reload_due),
+            (Some(interface), _) => Err(MethodErr::no_interface(interface)),
+        }
+    }
+
+    /// The methods of `org.ogage.Daemon`, which run control commands.
+    fn daemon_call(
+        &mut self,
+        message: &Message,
+        member: &str,
+        registry: &Registry,
+        idle: &mut IdleTimer,
+        reload_due: &mut Option<Instant>,
+    ) -> Result<Message, MethodErr> {
+        let command = match member {
+            "Inhibit" => {
+                let (what, who, why): (&str, &str, &str) = message.read3()?;
+                let kind = InhibitKind::from_name(what).ok_or_else(|| MethodErr::invalid_arg(what))?;
+                let fd = self.inhibit(registry, idle, kind, who, why).map_err(|e| MethodErr::failed(&e))?;
+                return Ok(message.method_return().append1(fd));
+            }
+            "ListInhibitors" => {
+                let inhibitors: Vec<(&str, &str, &str, u64)> = idle
+                    .inhibitors()
+                    .iter()
+                    .map(|inhibitor| (inhibitor.kind.name(), inhibitor.name.as_str(), inhibitor.reason.as_str(), inhibitor.since.elapsed().as_secs()))
+                    .collect();
+                return Ok(message.method_return().append1(inhibitors));
+            }
+            "SetVolume" => Command::Volume(Level::Set(percent(message)?)),
+            "SetBrightness" => Command::Brightness(Level::Set(percent(message)?)),
+            "Suspend" => Command::Action(Action::Suspend),
+            "SetPerformanceProfile" => Command::Perf(message.read1::<&str>()?.to_string()),
+            "RunAction" => {
+                let name: &str = message.read1()?;
+                Command::Action(Action::from_name(name).ok_or_else(|| MethodErr::invalid_arg(name))?)
+            }
+            _ => return Err(MethodErr::no_method(member)),
+        };
+        // The level methods answer with what was actually set
+        let level: Option<fn() -> Result<u32, Error>> = match &command {
+            Command::Volume(_) => Some(get_volume),
+            Command::Brightness(_) => Some(get_brightness),
+            _ => None,
+        };
+
+        execute(command, self.token, idle, reload_due).map_err(|e| MethodErr::failed(&e))?;
+        match level {
+            Some(level) => Ok(message.method_return().append1(level().map_err(|e| MethodErr::failed(&e))?)),
+            None => Ok(message.method_return()),
+        }
+    }
+
+    fn readable(&self) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.channel.watch().fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut fd, 1, 0) > 0 }
+    }
+}
+
+impl Value {
+    fn variant(&self) -> Variant<Box<dyn RefArg>> {
+        match self {
+            Value::U32(value) => Variant(Box::new(*value)),
+            Value::String(value) => Variant(Box::new(value.clone())),
+            Value::Bool(value) => Variant(Box::new(*value)),
+        }
+    }
+}
+
+/// The properties which can be read right now.
+fn properties(idle: &IdleTimer) -> Vec<(&'static str, Value)> {
+    let settings = settings();
+    let mut properties = Vec::new();
+
+    if let Ok(volume) = get_volume() {
+        properties.push(("Volume", Value::U32(volume)));
+    }
+    if let Ok(brightness) = get_brightness() {
+        properties.push(("Brightness", Value::U32(brightness)));
+    }
+    if let Some(profile) = settings.performance.active() {
+        properties.push(("PerformanceProfile", Value::String(profile)));
+    }
+    properties.extend(state_properties(idle));
+    if let Ok(status) = battery_status() {
+        properties.push(("BatteryStatus", Value::String(status.name().to_string())));
+    }
+    properties
+}
+
+/// The properties ogage keeps track of itself, which cost nothing to read.
+fn state_properties(idle: &IdleTimer) -> Vec<(&'static str, Value)> {
+    vec![
+        ("Dimmed", Value::Bool(idle.dimmed())),
+        ("ScreenOff", Value::Bool(SCREEN_OFF_BRIGHTNESS.load(Ordering::SeqCst) != 0)),
+    ]
+}
+
+/// The level property an event tells the new value of, `None` for the
+/// value when it couldn't be read.
+fn changed_property(name: &str, event: &Json) -> Option<(&'static str, Option<Value>)> {
+    let (property, field) = match name {
+        "action" => {
+            let action = match event.get("action") {
+                Some(Json::String(action)) => action.as_str(),
+                _ => return None,
+            };
+            let property = match Action::from_name(action) {
+                Some(Action::BrightUp | Action::BrightDown | Action::DarkOn | Action::DarkOff | Action::ScreenToggle) => "Brightness",
+                Some(Action::VolUp | Action::VolDown | Action::Mute | Action::VolNorm) => "Volume",
+                Some(Action::PerfMax | Action::PerfNorm | Action::PerfCycle) => "PerformanceProfile",
+                Some(_) => return None,
+                // Control commands
+                None => match action {
+                    "volume" | "mute" | "unmute" => "Volume",
+                    "brightness" => "Brightness",
+                    "perf" => "PerformanceProfile",
+                    _ => return None,
+                },
+            };
+            (property, "new")
+        }
+        "volume_changed" => ("Volume", "volume"),
+        "dim_entered" | "dim_left" => ("Brightness", "brightness"),
+        _ => return None,
+    };
+
+    let value = match event.get(field)? {
+        Json::Number(value) => u32::try_from(*value).ok().map(Value::U32),
+        Json::String(value) => Some(Value::String(value.clone())),
+        _ => None,
+    };
+    Some((property, value))
+}
+
+fn check_interface(interface: &str) -> Result<(), MethodErr> {
+    if interface == SERVICE_NAME {
+        Ok(())
+    } else {
+        Err(MethodErr::no_interface(interface))
+    }
+}
+
+fn percent(message: &Message) -> Result<u32, MethodErr> {
+    match message.read1()? {
+        percent if percent <= 100 => Ok(percent),
+        percent => Err(MethodErr::invalid_arg(&percent)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlight::Backlight;
+    use crate::mixer::Mixer;
+    use crate::{Settings, SETTINGS};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use mio::{Events, Poll};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command as Process, Stdio};
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// A private session bus, stopped when dropped.
+    struct TestBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl TestBus {
+        fn start() -> io::Result<TestBus> {
+            let mut daemon = Process::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork", "--nopidfile"])
+                .stdout(Stdio::piped())
+                .spawn()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address)?;
+            Ok(TestBus {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// A mixer or a backlight which only keeps the level.
+    struct Level(AtomicU32);
+
+    impl Mixer for Level {
+        fn volume(&self) -> io::Result<u32> {
+            Ok(self.0.load(Ordering::SeqCst))
+        }
+        fn set_volume(&self, percent: u32) -> io::Result<()> {
+            self.0.store(percent, Ordering::SeqCst);
+            Ok(())
+        }
+        fn set_muted(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+        fn describe(&self) -> String {
+            "test".to_string()
+        }
+    }
+
+    impl Backlight for Level {
+        fn brightness(&self) -> io::Result<u32> {
+            Ok(self.0.load(Ordering::SeqCst))
+        }
+        fn set_brightness(&self, percent: u32) -> io::Result<()> {
+            self.0.store(percent, Ordering::SeqCst);
+            Ok(())
+        }
+        fn describe(&self) -> String {
+            "test".to_string()
+        }
+    }
+
+    /// What a client sees of the service.
+    fn client(address: &str) -> Result<(), dbus::Error> {
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        let connection = Connection::from(channel);
+        let proxy = Proxy::new(SERVICE_NAME, OBJECT_PATH, Duration::from_secs(5), &connection);
+
+        let volume: u32 = proxy.get(SERVICE_NAME, "Volume")?;
+        assert_eq!(volume, 50);
+        proxy.method_call::<(), _, _, _>(SERVICE_NAME, "RunAction", ("vol_up",))?;
+        let raised: u32 = proxy.get(SERVICE_NAME, "Volume")?;
+        assert!(raised > volume, "vol_up left the volume at {}", raised);
+        let unknown = proxy.method_call::<(), _, _, _>(SERVICE_NAME, "RunAction", ("vol_sideways",)).unwrap_err();
+        assert_eq!(unknown.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+
+        let (brightness,): (u32,) = proxy.method_call(SERVICE_NAME, "SetBrightness", (40u32,))?;
+        assert_eq!(brightness, 40);
+        let brightness: u32 = proxy.get(SERVICE_NAME, "Brightness")?;
+        assert_eq!(brightness, 40);
+        let dimmed: bool = proxy.get(SERVICE_NAME, "Dimmed")?;
+        assert!(!dimmed);
+
+        let (fd,): (OwnedFd,) = proxy.method_call(SERVICE_NAME, "Inhibit", ("dim", "test", "testing"))?;
+        let (inhibitors,): (Vec<(String, String, String, u64)>,) = proxy.method_call(SERVICE_NAME, "ListInhibitors", ())?;
+        assert_eq!(inhibitors.len(), 1);
+        assert_eq!((inhibitors[0].0.as_str(), inhibitors[0].1.as_str(), inhibitors[0].2.as_str()), ("dim", "test", "testing"));
+
+        // Closing the descriptor releases the inhibitor
+        drop(fd);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let (inhibitors,): (Vec<(String, String, String, u64)>,) = proxy.method_call(SERVICE_NAME, "ListInhibitors", ())?;
+            if inhibitors.is_empty() {
+                return Ok(());
+            }
+            assert!(Instant::now() < deadline, "the inhibitor outlived its descriptor");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn serves_a_client() {
+        let bus = match TestBus::start() {
+            Ok(bus) => bus,
+            Err(e) => {
+                println!("Skipped, can't start dbus-daemon: {}", e);
+                return;
+            }
+        };
+        // libdbus reads the bus addresses once, before the logind backend
+        // of the settings connects
+        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &bus.address);
+        let settings = Settings {
+            mixer: Arc::new(Level(AtomicU32::new(50))),
+            backlight: Arc::new(Level(AtomicU32::new(100))),
+            ..Settings::load(None)
+        };
+        *SETTINGS.write().unwrap() = Arc::new(settings);
+
+        let mut poll = Poll::new().unwrap();
+        let mut idle = IdleTimer::new();
+        let mut service = DbusService::connect(poll.registry(), Token(0), Token(1), ServiceBus::Session, &idle).unwrap();
+
+        let address = bus.address.clone();
+        let client = thread::spawn(move || client(&address));
+        let mut events = Events::with_capacity(16);
+        let mut reload_due = None;
+        while !client.is_finished() {
+            poll.poll(&mut events, Some(Duration::from_millis(50))).unwrap();
+            for event in events.iter() {
+                if event.token() == Token(0) {
+                    assert!(service.dispatch(poll.registry(), &mut idle, &mut reload_due));
+                } else if service.owns(event.token()) {
+                    service.pipe_event(poll.registry(), event.token(), &mut idle);
+                }
+            }
+            service.publish(&idle);
+        }
+        let result = client.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        service.close(poll.registry(), &mut idle);
+        result.unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+use evdev::enums::EventCode;
+use evdev::{Device, InputEvent};
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use crate::grab::HotkeyGrab;
+use crate::inotify::Inotify;
+
+static INPUT_DIR: &str = "/dev/input";
+static SYS_INPUT_DIR: &str = "/sys/class/input";
+
+/// A device include/exclude rule: either a `vendor:product` pair of hex IDs
+/// or a device name, where `*` matches any run of characters.
+#[derive(Clone)]
+pub enum DeviceRule {
+    Id(u16, u16),
+    Name(String),
+}
+
+/// Decides which event nodes are worth listening to.
+pub struct DeviceFilter {
+    /// Codes ogage reacts to; a device needs at least one of them.
+    pub codes: Vec<EventCode>,
+    /// When not empty, only devices matching one of these rules are used.
+    pub include: Vec<DeviceRule>,
+    /// Devices matching any of these rules are never used.
+    pub exclude: Vec<DeviceRule>,
+}
+
+pub struct InputDevice {
+    pub path: PathBuf,
+    pub dev: Device,
+    fd: RawFd,
+    grab: Option<HotkeyGrab>,
+}
+
+impl InputDevice {
+    /// Passes an event on to the uinput copy of the device when hotkey
+    /// combos are being hidden, see `HotkeyGrab`.
+    pub fn pass_through(&mut self, ev: &InputEvent, hotkey: &EventCode, consumed: bool) {
+        if let Some(grab) = self.grab.as_mut() {
+            if let Err(e) = grab.event(ev, hotkey, consumed) {
+                println!("Can't pass events of {} through: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// The set of evdev nodes ogage listens to. Nodes are found by scanning
+/// `/dev/input` and kept up to date through inotify, so pads that show up
+/// or go away after startup are handled too.
+pub struct InputDevices {
+    devs: HashMap<Token, InputDevice>,
+    next_token: usize,
+    inotify: Inotify,
+    filter: DeviceFilter,
+    /// Pads sending this key are grabbed, see `HotkeyGrab`.
+    grab_hotkey: Option<EventCode>,
+}
+
+impl InputDevices {
+    /// Watches `/dev/input` and registers the watch under `dir_token`.
+    /// Device tokens are handed out from `first_token` onwards.
+    pub fn new(
+        registry: &Registry,
+        dir_token: Token,
+        first_token: Token,
+        filter: DeviceFilter,
+    ) -> io::Result<InputDevices> {
+        let inotify = Inotify::new()?;
+        // IN_ATTRIB catches nodes that were not readable yet when created,
+        // until udev fixes their permissions.
+        inotify.add_watch(Path::new(INPUT_DIR), libc::IN_CREATE | libc::IN_ATTRIB | libc::IN_DELETE)?;
+        registry.register(&mut SourceFd(&inotify.as_raw_fd()), dir_token, Interest::READABLE)?;
+
+        Ok(InputDevices {
+            devs: HashMap::new(),
+            next_token: first_token.0,
+            inotify,
+            filter,
+            grab_hotkey: None,
+        })
+    }
+
+    /// Makes the devices added from now on which have `hotkey` hide hotkey
+    /// combos from other programs, see `HotkeyGrab`.
+    pub fn grab_on_hotkey(&mut self, hotkey: EventCode) {
+        self.grab_hotkey = Some(hotkey);
+    }
+
+    /// Switches to the filter and grab hotkey of a new configuration. Open
+    /// devices the filter now rejects are closed and the ones it now accepts
+    /// opened; the others stay, only reopened to start or stop grabbing.
+    /// Returns the tokens of the devices closed.
+    pub fn reconfigure(&mut self, registry: &Registry, filter: DeviceFilter, grab_hotkey: Option<EventCode>) -> Vec<Token> {
+        self.filter = filter;
+        self.grab_hotkey = grab_hotkey;
+
+        let stale: Vec<Token> = self
+            .devs
+            .iter()
+            .filter(|(_, input)| {
+                let grabs = self.grab_hotkey.as_ref().is_some_and(|hotkey| input.dev.has_event_code(hotkey));
+                self.filter.check(&input.dev).is_err() || grabs != input.grab.is_some()
+            })
+            .map(|(token, _)| *token)
+            .collect();
+        for token in stale.iter() {
+            self.remove(registry, *token);
+        }
+
+        if let Err(e) = self.scan(registry) {
+            println!("Can't scan the input devices: {}", e);
+        }
+        stale
+    }
+
+    /// Opens every event node of `/dev/input` which isn't open yet.
+    pub fn scan(&mut self, registry: &Registry) -> io::Result<()> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(INPUT_DIR)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_event_node(path))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            self.add(registry, &path);
+        }
+
+        Ok(())
+    }
+
+    /// Handles the pending inotify events of `/dev/input`.
+    pub fn hotplug(&mut self, registry: &Registry) {
+        let events = match self.inotify.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                println!("Can't read {} events: {}", INPUT_DIR, e);
+                return;
+            }
+        };
+
+        for event in events {
+            let path = match event.name {
+                Some(name) => Path::new(INPUT_DIR).join(name),
+                None => continue,
+            };
+            if !is_event_node(&path) {
+                continue;
+            }
+
+            if event.mask & libc::IN_DELETE != 0 {
+                if let Some(token) = self.token_of(&path) {
+                    self.remove(registry, token);
+                }
+            } else {
+                self.add(registry, &path);
+            }
+        }
+    }
+
+    /// Whether any of the open devices can send `code`.
+    pub fn has_code(&self, code: &EventCode) -> bool {
+        self.devs.values().any(|input| input.dev.has_event_code(code))
+    }
+
+    pub fn get_mut(&mut self, token: Token) -> Option<&mut InputDevice> {
+        self.devs.get_mut(&token)
+    }
+
+    pub fn remove(&mut self, registry: &Registry, token: Token) {
+        if let Some(input) = self.devs.remove(&token) {
+            let _ = registry.deregister(&mut SourceFd(&input.fd));
+            println!("Removed device {}", input.path.display());
+        }
+    }
+
+    fn add(&mut self, registry: &Registry, path: &Path) {
+        if self.token_of(path).is_some() || self.is_own_uinput(path) {
+            return;
+        }
+
+        match open_device(path) {
+            Ok((mut dev, fd)) => {
+                if let Err(reason) = self.filter.check(&dev) {
+                    println!(
+                        "Skipped device {} ({} {:04x}:{:04x}): {}",
+                        path.display(),
+                        dev.name().unwrap_or("unnamed"),
+                        dev.vendor_id(),
+                        dev.product_id(),
+                        reason
+                    );
+                    return;
+                }
+
+                let token = Token(self.next_token);
+                if let Err(e) = registry.register(&mut SourceFd(&fd), token, Interest::READABLE) {
+                    println!("Can't watch device {}: {}", path.display(), e);
+                    return;
+                }
+                self.next_token += 1;
+                println!(
+                    "Added device {} ({} {:04x}:{:04x})",
+                    path.display(),
+                    dev.name().unwrap_or("unnamed"),
+                    dev.vendor_id(),
+                    dev.product_id()
+                );
+                let grab = self.grab_hotkey.as_ref().filter(|hotkey| dev.has_event_code(hotkey)).and_then(|_| {
+                    HotkeyGrab::new(&mut dev)
+                        .map_err(|e| println!("Can't grab {} to hide hotkey combos: {}", path.display(), e))
+                        .ok()
+                });
+                self.devs.insert(token, InputDevice { path: path.to_path_buf(), dev, fd, grab });
+            }
+            // Not accessible yet, IN_ATTRIB will tell when it is
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => (),
+            Err(e) => println!("Can't open device {}: {}", path.display(), e),
+        }
+    }
+
+    fn is_own_uinput(&self, path: &Path) -> bool {
+        self.devs
+            .values()
+            .filter_map(|input| input.grab.as_ref())
+            .any(|grab| grab.devnode().is_some_and(|devnode| Path::new(devnode) == path))
+    }
+
+    fn token_of(&self, path: &Path) -> Option<Token> {
+        self.devs
+            .iter()
+            .find(|(_, input)| input.path == path)
+            .map(|(token, _)| *token)
+    }
+}
+
+impl DeviceRule {
+    pub fn parse(rule: &str) -> DeviceRule {
+        let rule = rule.trim();
+        let mut ids = rule.splitn(2, ':');
+        if let (Some(vendor), Some(product)) = (ids.next(), ids.next()) {
+            if let (Ok(vendor), Ok(product)) = (u16::from_str_radix(vendor, 16), u16::from_str_radix(product, 16)) {
+                return DeviceRule::Id(vendor, product);
+            }
+        }
+
+        DeviceRule::Name(rule.to_string())
+    }
+
+    /// Parses a comma separated list of rules.
+    pub fn parse_list(rules: &str) -> Vec<DeviceRule> {
+        rules
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(DeviceRule::parse)
+            .collect()
+    }
+
+    pub fn matches_id(&self, name: &str, vendor: u16, product: u16) -> bool {
+        match self {
+            DeviceRule::Id(rule_vendor, rule_product) => vendor == *rule_vendor && product == *rule_product,
+            DeviceRule::Name(pattern) => glob_match(pattern, name),
+        }
+    }
+
+    fn matches(&self, dev: &Device) -> bool {
+        self.matches_id(dev.name().unwrap_or(""), dev.vendor_id(), dev.product_id())
+    }
+}
+
+impl DeviceFilter {
+    fn check(&self, dev: &Device) -> Result<(), &'static str> {
+        if self.exclude.iter().any(|rule| rule.matches(dev)) {
+            return Err("excluded");
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|rule| rule.matches(dev)) {
+            return Err("not included");
+        }
+        if !self.codes.iter().any(|code| dev.has_event_code(code)) {
+            return Err("no hotkey, power or action buttons");
+        }
+
+        Ok(())
+    }
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Name, vendor and product of every input device, read from sysfs so the
+/// event nodes don't have to be opened.
+pub fn input_ids() -> Vec<(String, u16, u16)> {
+    let read = |path: PathBuf| fs::read_to_string(path).map(|s| s.trim().to_string()).unwrap_or_default();
+    let mut ids = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(SYS_INPUT_DIR) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            if !is_event_node(&entry.path()) {
+                continue;
+            }
+            let device = entry.path().join("device");
+            let name = read(device.join("name"));
+            let vendor = u16::from_str_radix(&read(device.join("id/vendor")), 16).unwrap_or(0);
+            let product = u16::from_str_radix(&read(device.join("id/product")), 16).unwrap_or(0);
+            ids.push((name, vendor, product));
+        }
+    }
+
+    ids
+}
+
+fn is_event_node(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("event"))
+}
+
+/// Opens the node read-only, grabbing needs no write access.
+fn open_device(path: &Path) -> io::Result<(Device, RawFd)> {
+    let fd = File::open(path)?;
+    let raw_fd = fd.as_raw_fd();
+    let mut dev = Device::new().ok_or_else(|| io::Error::other("Can't allocate evdev device"))?;
+    dev.set_fd(fd)?;
+    Ok((dev, raw_fd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("GO-Super Gamepad", "GO-Super Gamepad"));
+        assert!(!glob_match("GO-Super Gamepad", "GO-Super Gamepad 2"));
+        assert!(glob_match("GO-Super*", "GO-Super Gamepad"));
+        assert!(glob_match("*Gamepad", "GO-Super Gamepad"));
+        assert!(glob_match("*rk3326-odroid-go2", "hardkernel,rk3326-odroid-go2"));
+        assert!(!glob_match("*rk3326-odroid-go2", "hardkernel,rk3326-odroid-go2-v11"));
+        assert!(glob_match("*ODROID*v11*", "Hardkernel ODROID-GO2 v11 board"));
+        assert!(glob_match("a*b*b", "abxbb"));
+        assert!(!glob_match("a*b*c", "abxbb"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("", "x"));
+    }
+
+    #[test]
+    fn parses_device_rules() {
+        assert!(matches!(DeviceRule::parse(" 1209:0A3f "), DeviceRule::Id(0x1209, 0x0a3f)));
+        assert!(matches!(DeviceRule::parse("GO-Super Gamepad*"), DeviceRule::Name(name) if name == "GO-Super Gamepad*"));
+        assert!(matches!(DeviceRule::parse("Mouse:Left"), DeviceRule::Name(name) if name == "Mouse:Left"));
+
+        let rules = DeviceRule::parse_list("1209:0a3f, ,*Keyboard*,");
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].matches_id("Pad", 0x1209, 0x0a3f));
+        assert!(!rules[0].matches_id("Pad", 0x1209, 0x0a40));
+        assert!(rules[1].matches_id("USB Keyboard", 0, 0));
+    }
+}
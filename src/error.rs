@@ -0,0 +1,95 @@
+use std::fmt;
+use std::io;
+
+/// What can go wrong in ogage. None of it is fatal once the daemon runs:
+/// errors are logged and ogage carries on with defaults.
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read or written.
+    File { path: String, source: io::Error },
+    /// A configuration file isn't a valid properties file.
+    ConfigSyntax { file: String },
+    /// A configuration value doesn't have the expected format.
+    ConfigValue {
+        file: String,
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    /// A device profile is incomplete or invalid.
+    Profile { file: String, message: String },
+    /// A backend (backlight, mixer, radios, power) failed.
+    Backend {
+        backend: String,
+        operation: &'static str,
+        source: io::Error,
+    },
+    /// A kernel interface reported a value ogage doesn't know.
+    UnexpectedValue { path: String, value: String },
+    /// Some settings of a performance profile couldn't be applied.
+    PerfProfile { name: String, errors: Vec<String> },
+    /// Something asked for by name doesn't exist.
+    Unknown { what: &'static str, name: String },
+    /// Setting up the event loop failed.
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn file(path: &str, source: io::Error) -> Error {
+        Error::File {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn backend(backend: String, operation: &'static str, source: io::Error) -> Error {
+        Error::Backend {
+            backend,
+            operation,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::File { path, source } => write!(f, "{}: {}", path, source),
+            Error::ConfigSyntax { file } => write!(f, "can't parse the properties of '{}'", file),
+            Error::ConfigValue {
+                file,
+                key,
+                value,
+                expected,
+            } => write!(f, "{}: {}='{}' should be {}", file, key, value, expected),
+            Error::Profile { file, message } => write!(f, "device profile {}: {}", file, message),
+            Error::Backend {
+                backend,
+                operation,
+                source,
+            } => write!(f, "can't {} through {}: {}", operation, backend, source),
+            Error::UnexpectedValue { path, value } => write!(f, "{}: unexpected value '{}'", path, value),
+            Error::PerfProfile { name, errors } => {
+                write!(f, "performance profile '{}': {}", name, errors.join(", "))
+            }
+            Error::Unknown { what, name } => write!(f, "no {} '{}'", what, name),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::File { source, .. } | Error::Backend { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,201 @@
+use mio::event::Event;
+use mio::net::UnixStream;
+use mio::{Interest, Registry, Token};
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::battery_status;
+use crate::json::Json;
+
+/// Bytes of unsent events a subscriber may fall behind on before it is dropped.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+// How often the battery status is looked at while a subscriber listens
+static BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Subscribers> = Mutex::new(Subscribers {
+        streams: Vec::new(),
+        count: 0,
+    });
+    /// Events waiting for the D-Bus service, `None` when it doesn't run.
+    static ref QUEUE: Mutex<Option<Vec<(String, Json)>>> = Mutex::new(None);
+}
+
+struct Subscribers {
+    streams: Vec<Subscriber>,
+    /// Subscribers so far, which numbers their tokens.
+    count: usize,
+}
+
+/// A control connection which turned into an event stream.
+struct Subscriber {
+    token: Token,
+    stream: UnixStream,
+    pending: Vec<u8>,
+}
+
+/// Notices what no code path of ogage tells about: the battery status
+/// changing and the system coming back from a suspend, whoever started it.
+pub struct StateWatch {
+    battery: Option<&'static str>,
+    /// Time spent suspended since boot.
+    asleep: Duration,
+    next_battery_check: Instant,
+}
+
+impl Subscriber {
+    /// Writes what it can, false when the subscriber is gone or too slow.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(len) => {
+                    self.pending.drain(..len);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return self.pending.len() <= MAX_PENDING_BYTES,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Sends the events from now on to `stream`, after `pending`. The stream
+/// isn't read anymore but is watched under a token from `first_token` on,
+/// so that `subscriber_event` writes what waits once the subscriber reads
+/// again and drops it when it hangs up.
+pub fn subscribe(registry: &Registry, first_token: Token, stream: UnixStream, pending: Vec<u8>) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    let token = Token(first_token.0 + subscribers.count);
+    let mut subscriber = Subscriber { token, stream, pending };
+    if !subscriber.flush() {
+        return;
+    }
+    // Edge triggered, writing only has to go on when the socket drained
+    if let Err(e) = registry.register(&mut subscriber.stream, token, Interest::READABLE | Interest::WRITABLE) {
+        println!("Can't watch event subscriber: {}", e);
+        return;
+    }
+    subscribers.count += 1;
+    subscribers.streams.push(subscriber);
+}
+
+/// Whether the event of `token` is for a subscriber.
+pub fn owns(token: Token) -> bool {
+    SUBSCRIBERS.lock().unwrap().streams.iter().any(|subscriber| subscriber.token == token)
+}
+
+/// Writes the events a subscriber fell behind on, or drops it when it hung
+/// up or is too slow.
+pub fn subscriber_event(registry: &Registry, event: &Event) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    let index = match subscribers.streams.iter().position(|subscriber| subscriber.token == event.token()) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let subscriber = &mut subscribers.streams[index];
+    // A subscriber which only shut its side down, like `echo subscribe |
+    // socat ...`, still reads
+    let gone = event.is_write_closed() || event.is_error();
+    if gone || (event.is_writable() && !subscriber.flush()) {
+        let mut subscriber = subscribers.streams.swap_remove(index);
+        let _ = registry.deregister(&mut subscriber.stream);
+    }
+}
+
+/// Keeps the events from now on for `take_queued`, or stops doing so.
+#[cfg(feature = "dbus-service")]
+pub fn queue_events(enabled: bool) {
+    *QUEUE.lock().unwrap() = Some(Vec::new()).filter(|_| enabled);
+}
+
+/// The events queued since the last call.
+#[cfg(feature = "dbus-service")]
+pub fn take_queued() -> Vec<(String, Json)> {
+    QUEUE.lock().unwrap().as_mut().map(mem::take).unwrap_or_default()
+}
+
+/// Whether a control connection subscribed. The D-Bus service only gets
+/// the events which happen anyway, without polling for more.
+fn has_subscribers() -> bool {
+    !SUBSCRIBERS.lock().unwrap().streams.is_empty()
+}
+
+/// Whether anybody listens, to skip the work of describing events.
+pub fn subscribed() -> bool {
+    !SUBSCRIBERS.lock().unwrap().streams.is_empty() || QUEUE.lock().unwrap().is_some()
+}
+
+/// Sends `{"event":<name>,...}` with the fields to every subscriber, and
+/// queues it for the D-Bus service.
+pub fn emit(name: &str, fields: Vec<(&str, Json)>) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    let mut queue = QUEUE.lock().unwrap();
+    if subscribers.streams.is_empty() && queue.is_none() {
+        return;
+    }
+
+    let event = Json::object(std::iter::once(("event", Json::string(name))).chain(fields).collect());
+    let line = format!("{}\n", event);
+    if let Some(queue) = queue.as_mut() {
+        queue.push((name.to_string(), event));
+    }
+    // Dropping a stream takes it out of the poll as well
+    subscribers.streams.retain_mut(|subscriber| {
+        subscriber.pending.extend_from_slice(line.as_bytes());
+        subscriber.flush()
+    });
+}
+
+impl StateWatch {
+    pub fn new() -> StateWatch {
+        StateWatch {
+            battery: battery_status().ok().map(|status| status.name()),
+            asleep: asleep(),
+            next_battery_check: Instant::now() + BATTERY_POLL_INTERVAL,
+        }
+    }
+
+    /// Time left until the battery is due, while a subscriber listens.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        if has_subscribers() {
+            Some(self.next_battery_check.saturating_duration_since(Instant::now()))
+        } else {
+            None
+        }
+    }
+
+    pub fn check(&mut self) {
+        let asleep = asleep();
+        let slept = asleep.saturating_sub(self.asleep);
+        self.asleep = asleep;
+        if slept >= Duration::from_secs(1) {
+            emit("resumed", vec![("slept_seconds", slept.as_secs().into())]);
+        }
+
+        if !has_subscribers() || Instant::now() < self.next_battery_check {
+            return;
+        }
+        self.next_battery_check = Instant::now() + BATTERY_POLL_INTERVAL;
+        let battery = battery_status().ok().map(|status| status.name());
+        if battery != self.battery {
+            self.battery = battery;
+            emit("battery", vec![("status", battery.map(Json::string).into())]);
+        }
+    }
+}
+
+/// The boot time clock goes on during a suspend, the monotonic one doesn't.
+fn asleep() -> Duration {
+    let clock = |id| unsafe {
+        let mut time: libc::timespec = mem::zeroed();
+        libc::clock_gettime(id, &mut time);
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    };
+    clock(libc::CLOCK_BOOTTIME).saturating_sub(clock(libc::CLOCK_MONOTONIC))
+}
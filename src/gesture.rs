@@ -0,0 +1,286 @@
+use evdev::enums::EventCode;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::action::Action;
+use crate::profile::parse_key_code;
+
+/// Prefix of the `ogage.conf` keys giving gestures to any key, e.g.
+/// `gesture.BTN_MODE.double_press=screen_toggle`.
+pub static GESTURE_PREFIX: &str = "gesture.";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Gesture {
+    /// Fires as soon as the key goes down.
+    Press,
+    /// A press released before the long press time, with no second press
+    /// following within the double press interval.
+    SinglePress,
+    /// A second press within the double press interval.
+    DoublePress,
+    /// The key held for the long press time.
+    LongPress,
+}
+
+pub static GESTURES: [Gesture; 4] = [
+    Gesture::Press,
+    Gesture::SinglePress,
+    Gesture::DoublePress,
+    Gesture::LongPress,
+];
+
+#[derive(Clone)]
+pub struct GestureTiming {
+    pub long_press: Duration,
+    /// Presses closer than this don't make a double press, the second one
+    /// starts over instead.
+    pub double_press_min: Duration,
+    pub double_press_max: Duration,
+}
+
+impl Default for GestureTiming {
+    fn default() -> GestureTiming {
+        GestureTiming {
+            long_press: Duration::from_millis(1000),
+            double_press_min: Duration::from_millis(0),
+            double_press_max: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Recognizes gestures on a single key and maps them to actions.
+#[derive(Clone)]
+pub struct GestureKey {
+    pub code: EventCode,
+    pub actions: Vec<(Gesture, Action)>,
+    pub timing: GestureTiming,
+    pressed_at: Option<Instant>,
+    /// Start of the previous short press, while a double press may follow.
+    last_press: Option<Instant>,
+    /// Set when the current press already made a gesture.
+    consumed: bool,
+    single_due: Option<Instant>,
+}
+
+impl Gesture {
+    /// Name used for the gesture in configuration files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Gesture::Press => "press",
+            Gesture::SinglePress => "single_press",
+            Gesture::DoublePress => "double_press",
+            Gesture::LongPress => "long_press",
+        }
+    }
+}
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl GestureKey {
+    pub fn new(code: EventCode, actions: Vec<(Gesture, Action)>, timing: GestureTiming) -> GestureKey {
+        GestureKey {
+            code,
+            actions,
+            timing,
+            pressed_at: None,
+            last_press: None,
+            consumed: false,
+            single_due: None,
+        }
+    }
+
+    /// Feeds a press (1) or release (0) of the key and returns the actions
+    /// to run.
+    pub fn key_event(&mut self, value: i32) -> Vec<Action> {
+        let now = Instant::now();
+        let mut actions = Vec::new();
+
+        match value {
+            1 => {
+                self.pressed_at = Some(now);
+                self.consumed = false;
+                self.single_due = None;
+                actions.extend(self.action(Gesture::Press));
+
+                if let Some(last) = self.last_press.take() {
+                    let interval = now - last;
+                    if self.handles(Gesture::DoublePress)
+                        && interval >= self.timing.double_press_min
+                        && interval <= self.timing.double_press_max
+                    {
+                        actions.extend(self.action(Gesture::DoublePress));
+                        self.consumed = true;
+                        return actions;
+                    }
+                }
+                self.last_press = Some(now);
+            }
+            0 => {
+                if self.pressed_at.take().is_none() || self.consumed {
+                    return actions;
+                }
+
+                if self.handles(Gesture::SinglePress) {
+                    let double_deadline = self
+                        .last_press
+                        .filter(|_| self.handles(Gesture::DoublePress))
+                        .map(|last| last + self.timing.double_press_max);
+                    match double_deadline {
+                        Some(deadline) if deadline > now => self.single_due = Some(deadline),
+                        _ => actions.extend(self.action(Gesture::SinglePress)),
+                    }
+                }
+            }
+            // Kernel auto-repeat
+            _ => (),
+        }
+
+        actions
+    }
+
+    /// Time left until a long press or a pending single press is due.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let long_due = self
+            .pressed_at
+            .filter(|_| !self.consumed && self.handles(Gesture::LongPress))
+            .map(|pressed_at| pressed_at + self.timing.long_press);
+
+        match (long_due, self.single_due) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+        .map(|due| due.saturating_duration_since(now))
+    }
+
+    /// Fires long presses and single presses which are due.
+    pub fn check(&mut self) -> Vec<Action> {
+        let now = Instant::now();
+        let mut actions = Vec::new();
+
+        if let Some(pressed_at) = self.pressed_at {
+            if !self.consumed && self.handles(Gesture::LongPress) && pressed_at + self.timing.long_press <= now {
+                actions.extend(self.action(Gesture::LongPress));
+                self.consumed = true;
+                self.last_press = None;
+            }
+        }
+
+        if self.single_due.is_some_and(|due| due <= now) {
+            actions.extend(self.action(Gesture::SinglePress));
+            self.single_due = None;
+            self.last_press = None;
+        }
+
+        actions
+    }
+
+    fn handles(&self, gesture: Gesture) -> bool {
+        self.actions.iter().any(|(g, _)| *g == gesture)
+    }
+
+    fn action(&self, gesture: Gesture) -> Option<Action> {
+        self.actions.iter().find(|(g, _)| *g == gesture).map(|(_, action)| *action)
+    }
+}
+
+/// Reads the `<prefix><gesture>=<action>` keys of `properties`.
+pub fn parse_actions(properties: &HashMap<String, String>, prefix: &str, warnings: &mut Vec<String>) -> Vec<(Gesture, Action)> {
+    let mut actions = Vec::new();
+
+    for gesture in GESTURES.iter() {
+        let key = format!("{}{}", prefix, gesture.name());
+        if let Some(name) = properties.get(&key) {
+            match Action::from_name(name.trim()) {
+                Some(action) => actions.push((*gesture, action)),
+                None => warnings.push(format!("Ignoring {}: unknown action '{}'", key, name.trim())),
+            }
+        }
+    }
+
+    actions
+}
+
+/// Builds a recognizer for every key with `gesture.<key code>.<gesture>`
+/// entries in `properties`.
+pub fn parse_keys(properties: &HashMap<String, String>, warnings: &mut Vec<String>) -> Vec<GestureKey> {
+    let mut names = Vec::new();
+    for key in properties.keys() {
+        let rest = match key.strip_prefix(GESTURE_PREFIX) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        match rest.rsplit_once('.') {
+            Some((name, gesture)) if GESTURES.iter().any(|g| g.name() == gesture) => names.push(name),
+            _ => warnings.push(format!("Ignoring {}: expected {}<key code>.<gesture>", key, GESTURE_PREFIX)),
+        }
+    }
+    names.sort_unstable();
+    names.dedup();
+
+    let mut keys = Vec::new();
+    for name in names {
+        let code = match parse_key_code(name) {
+            Some(code) => code,
+            None => {
+                warnings.push(format!("Ignoring {}{}.*: unknown key code '{}'", GESTURE_PREFIX, name, name));
+                continue;
+            }
+        };
+        let actions = parse_actions(properties, &format!("{}{}.", GESTURE_PREFIX, name), warnings);
+        if !actions.is_empty() {
+            keys.push(GestureKey::new(code, actions, GestureTiming::default()));
+        }
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::enums::EV_KEY;
+
+    fn properties(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_actions_of_a_prefix() {
+        let properties = properties(&[("long_press", " suspend "), ("double_press", "explode"), ("press", "vol_up")]);
+        let mut warnings = Vec::new();
+        let actions = parse_actions(&properties, "", &mut warnings);
+        assert!(actions == vec![(Gesture::Press, Action::VolUp), (Gesture::LongPress, Action::Suspend)]);
+        assert_eq!(warnings, vec!["Ignoring double_press: unknown action 'explode'"]);
+    }
+
+    #[test]
+    fn parses_gesture_keys() {
+        let properties = properties(&[
+            ("gesture.BTN_MODE.single_press", "screen_toggle"),
+            ("gesture.BTN_MODE.long_press", "suspend"),
+            ("gesture.BTN_NOPE.press", "vol_up"),
+            ("gesture.BTN_SOUTH.triple_press", "vol_up"),
+            ("gesture.BTN_NORTH", "vol_up"),
+        ]);
+        let mut warnings = Vec::new();
+        let keys = parse_keys(&properties, &mut warnings);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].code, EventCode::EV_KEY(EV_KEY::BTN_MODE));
+        assert!(keys[0].actions == vec![(Gesture::SinglePress, Action::ScreenToggle), (Gesture::LongPress, Action::Suspend)]);
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            vec![
+                "Ignoring gesture.BTN_NOPE.*: unknown key code 'BTN_NOPE'",
+                "Ignoring gesture.BTN_NORTH: expected gesture.<key code>.<gesture>",
+                "Ignoring gesture.BTN_SOUTH.triple_press: expected gesture.<key code>.<gesture>",
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use evdev::enums::{EventCode, EventType, EV_SYN};
+use evdev::{Device, GrabMode, InputEvent, TimeVal, UInputDevice};
+use std::io;
+
+/// Keeps hotkey combos away from the other readers of a pad.
+///
+/// The pad is grabbed for as long as ogage has it open, and everything it
+/// sends goes on through a uinput copy, except the buttons of hotkey
+/// bindings. The hotkey press is held back until it is clear whether a
+/// combo follows: a tap of the hotkey, or the hotkey with a button of no
+/// binding, still reaches the other programs.
+///
+/// Grabbing only while the hotkey is down doesn't work: the hotkey press
+/// has reached the other readers before ogage sees it and can grab, and
+/// the releases of buttons held across the grab would go to the uinput
+/// copy, which those readers don't use, leaving the buttons stuck down.
+pub struct HotkeyGrab<W = UInputDevice> {
+    uinput: W,
+    /// Keys pressed on the uinput pad, their releases follow them there.
+    virtual_down: Vec<EventCode>,
+    /// The hotkey is down but the uinput pad didn't get it yet.
+    hotkey_held_back: bool,
+    /// A combo was pressed while the hotkey was held back, which keeps it
+    /// hidden until it is released.
+    combo_pressed: bool,
+}
+
+/// Where a `HotkeyGrab` passes the events on to.
+pub trait EventWriter {
+    fn write_event(&mut self, ev: &InputEvent) -> io::Result<()>;
+}
+
+impl EventWriter for UInputDevice {
+    fn write_event(&mut self, ev: &InputEvent) -> io::Result<()> {
+        UInputDevice::write_event(self, ev)
+    }
+}
+
+impl HotkeyGrab {
+    /// Creates the uinput copy of the pad and grabs the pad.
+    pub fn new(dev: &mut Device) -> io::Result<HotkeyGrab> {
+        let uinput = UInputDevice::create_from_device(dev)?;
+        dev.grab(GrabMode::Grab)?;
+        Ok(HotkeyGrab::with_writer(uinput))
+    }
+
+    /// Device node of the uinput pad, so it isn't picked up as an input
+    /// device of its own.
+    pub fn devnode(&self) -> Option<&str> {
+        self.uinput.devnode()
+    }
+}
+
+impl<W: EventWriter> HotkeyGrab<W> {
+    fn with_writer(uinput: W) -> HotkeyGrab<W> {
+        HotkeyGrab {
+            uinput,
+            virtual_down: Vec::new(),
+            hotkey_held_back: false,
+            combo_pressed: false,
+        }
+    }
+
+    /// Passes an event read from the pad on. `consumed` tells whether it
+    /// belongs to a hotkey binding.
+    pub fn event(&mut self, ev: &InputEvent, hotkey: &EventCode, consumed: bool) -> io::Result<()> {
+        if !ev.is_type(&EventType::EV_KEY) {
+            return self.uinput.write_event(ev);
+        }
+
+        if ev.event_code == *hotkey {
+            return match ev.value {
+                1 => {
+                    self.hotkey_held_back = true;
+                    self.combo_pressed = false;
+                    Ok(())
+                }
+                0 if self.hotkey_held_back => {
+                    self.hotkey_held_back = false;
+                    if self.combo_pressed {
+                        return Ok(());
+                    }
+                    // A tap of the hotkey alone
+                    self.write_key(hotkey, 1)?;
+                    self.write_key(hotkey, 0)
+                }
+                _ => self.forward(ev),
+            };
+        }
+
+        if ev.value == 1 {
+            if consumed {
+                self.combo_pressed |= self.hotkey_held_back;
+                return Ok(());
+            }
+            if self.hotkey_held_back && !self.combo_pressed {
+                self.hotkey_held_back = false;
+                self.write_key(hotkey, 1)?;
+            }
+        }
+        self.forward(ev)
+    }
+
+    /// Writes a key event unless it is the repeat or release of a press the
+    /// uinput pad didn't get.
+    fn forward(&mut self, ev: &InputEvent) -> io::Result<()> {
+        let known = self.virtual_down.contains(&ev.event_code);
+        match ev.value {
+            1 if !known => self.virtual_down.push(ev.event_code.clone()),
+            0 if known => self.virtual_down.retain(|code| *code != ev.event_code),
+            1 => (),
+            _ if !known => return Ok(()),
+            _ => (),
+        }
+        self.uinput.write_event(ev)
+    }
+
+    fn write_key(&mut self, code: &EventCode, value: i32) -> io::Result<()> {
+        self.forward(&InputEvent::new(&TimeVal::new(0, 0), code, value))?;
+        self.uinput
+            .write_event(&InputEvent::new(&TimeVal::new(0, 0), &EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::enums::{EV_ABS, EV_KEY};
+
+    /// Keeps what would go to the uinput pad, leaving out the reports.
+    #[derive(Default)]
+    struct Written(Vec<String>);
+
+    impl EventWriter for Written {
+        fn write_event(&mut self, ev: &InputEvent) -> io::Result<()> {
+            if !ev.is_type(&EventType::EV_SYN) {
+                self.0.push(format!("{} {}", ev.event_code, ev.value));
+            }
+            Ok(())
+        }
+    }
+
+    static HOTKEY: EventCode = EventCode::EV_KEY(EV_KEY::BTN_MODE);
+
+    /// Sends key events of (key, value, consumed) through a new grab and
+    /// returns what came out.
+    fn pass(events: &[(EV_KEY, i32, bool)]) -> Vec<String> {
+        let mut grab = HotkeyGrab::with_writer(Written::default());
+        for (key, value, consumed) in events {
+            let ev = InputEvent::new(&TimeVal::new(0, 0), &EventCode::EV_KEY(key.clone()), *value);
+            grab.event(&ev, &HOTKEY, *consumed).unwrap();
+        }
+        grab.uinput.0
+    }
+
+    #[test]
+    fn passes_other_input_on() {
+        assert_eq!(
+            pass(&[(EV_KEY::BTN_SOUTH, 1, false), (EV_KEY::BTN_SOUTH, 2, false), (EV_KEY::BTN_SOUTH, 0, false)]),
+            vec!["BTN_SOUTH 1", "BTN_SOUTH 2", "BTN_SOUTH 0"]
+        );
+
+        let mut grab = HotkeyGrab::with_writer(Written::default());
+        let ev = InputEvent::new(&TimeVal::new(0, 0), &EventCode::EV_ABS(EV_ABS::ABS_X), 512);
+        grab.event(&ev, &HOTKEY, false).unwrap();
+        assert_eq!(grab.uinput.0, vec!["ABS_X 512"]);
+    }
+
+    #[test]
+    fn holds_the_hotkey_back() {
+        // A tap goes on once let go
+        assert_eq!(pass(&[(EV_KEY::BTN_MODE, 1, false)]), Vec::<String>::new());
+        assert_eq!(pass(&[(EV_KEY::BTN_MODE, 1, false), (EV_KEY::BTN_MODE, 0, false)]), vec!["BTN_MODE 1", "BTN_MODE 0"]);
+
+        // With a button of no binding, both go on in order
+        assert_eq!(
+            pass(&[
+                (EV_KEY::BTN_MODE, 1, false),
+                (EV_KEY::BTN_MODE, 2, false),
+                (EV_KEY::BTN_SOUTH, 1, false),
+                (EV_KEY::BTN_MODE, 2, false),
+                (EV_KEY::BTN_SOUTH, 0, false),
+                (EV_KEY::BTN_MODE, 0, false),
+            ]),
+            vec!["BTN_MODE 1", "BTN_SOUTH 1", "BTN_MODE 2", "BTN_SOUTH 0", "BTN_MODE 0"]
+        );
+    }
+
+    #[test]
+    fn swallows_combos() {
+        assert_eq!(
+            pass(&[
+                (EV_KEY::BTN_MODE, 1, false),
+                (EV_KEY::BTN_TR, 1, true),
+                (EV_KEY::BTN_TR, 2, true),
+                (EV_KEY::BTN_TR, 0, true),
+                (EV_KEY::BTN_MODE, 0, false),
+            ]),
+            Vec::<String>::new()
+        );
+
+        // A button of no binding after a combo doesn't bring the hotkey
+        // back, and the combo button let go after the hotkey stays hidden
+        assert_eq!(
+            pass(&[
+                (EV_KEY::BTN_MODE, 1, false),
+                (EV_KEY::BTN_TR, 1, true),
+                (EV_KEY::BTN_SOUTH, 1, false),
+                (EV_KEY::BTN_MODE, 0, false),
+                (EV_KEY::BTN_TR, 0, false),
+                (EV_KEY::BTN_SOUTH, 0, false),
+            ]),
+            vec!["BTN_SOUTH 1", "BTN_SOUTH 0"]
+        );
+    }
+
+    #[test]
+    fn drops_what_the_pad_never_pressed() {
+        // Held before the grab, or pressed as a combo
+        assert_eq!(pass(&[(EV_KEY::BTN_SOUTH, 2, false), (EV_KEY::BTN_SOUTH, 0, false)]), Vec::<String>::new());
+
+        // A button held before the hotkey keeps going through
+        assert_eq!(
+            pass(&[
+                (EV_KEY::BTN_TR, 1, false),
+                (EV_KEY::BTN_MODE, 1, false),
+                (EV_KEY::BTN_TR, 0, true),
+                (EV_KEY::BTN_MODE, 0, false),
+            ]),
+            vec!["BTN_TR 1", "BTN_TR 0", "BTN_MODE 1", "BTN_MODE 0"]
+        );
+    }
+}
@@ -0,0 +1,771 @@
+use mio::Token;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::config::{AutoDimConfig, AutoSuspendConfig};
+use crate::error::Error;
+use crate::event;
+use crate::json::Json;
+use crate::mixer;
+use crate::processes::{self, ProcessMatch, ProcessRule};
+use crate::{
+    battery_status, create_es_brightness_lock, get_brightness, remove_es_brightness_lock,
+    set_brightness, settings, suspend, BatteryStatus,
+};
+
+// How often the battery is looked at while dimmed, so plugging the charger
+// in restores the brightness without having to touch the device.
+static CHARGE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// How often a process holding off a timeout is looked for again
+static PROCESS_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// How often sound playback found when a timeout was due is looked at again,
+// so the timeouts count from about when it stopped
+static PLAYBACK_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What an inhibitor keeps from happening, `Idle` being both.
+#[derive(Clone, Copy, PartialEq)]
+pub enum InhibitKind {
+    Dim,
+    Suspend,
+    Idle,
+}
+
+/// Holds off the idle timeouts on behalf of a client until it releases it
+/// or goes away. `owner` is the control connection or the descriptor the
+/// client holds.
+pub struct Inhibitor {
+    pub owner: Token,
+    pub kind: InhibitKind,
+    /// Who inhibits, e.g. the program, unique for an owner.
+    pub name: String,
+    pub reason: String,
+    pub since: Instant,
+}
+
+/// A running process which a rule of `auto_suspend.conf` or `auto_dim.conf`
+/// matched when the timeout was due. It holds the timeout off until no
+/// process matches anymore.
+pub struct ProcessInhibitor {
+    /// `Dim` or `Suspend`.
+    pub kind: InhibitKind,
+    pub process: ProcessMatch,
+    pub since: Instant,
+}
+
+/// What the idle timer reads and changes outside of itself: the clock, the
+/// battery, sound playback, the running processes, the backlight and the
+/// power backend. The tests replace it.
+pub trait IdleSystem {
+    fn now(&self) -> Instant;
+    /// Whether the battery charges or is full.
+    fn charging(&self) -> Result<bool, Error>;
+    fn playback_running(&self) -> bool;
+    fn find_process(&self, rules: &[ProcessRule]) -> Option<ProcessMatch>;
+    fn brightness(&self) -> Result<u32, Error>;
+    /// Sets the brightness, keeping EmulationStation from fighting over it.
+    fn set_brightness(&self, percent: u32) -> Result<(), Error>;
+    fn suspend(&self) -> Result<(), Error>;
+}
+
+/// The system as the daemon sees it, through the configured backends.
+pub struct Daemon;
+
+/// Keeps track of user activity and fires auto-dim and auto-suspend when
+/// their timeouts expire, whether or not any input event arrives.
+pub struct IdleTimer<S: IdleSystem = Daemon> {
+    system: S,
+    last_button_push: Instant,
+    last_charge: Instant,
+    last_playback: Instant,
+    /// Set while sound plays, as far as the last look found.
+    next_playback_check: Option<Instant>,
+    auto_dim_active: bool,
+    last_brightness: u32,
+    /// The battery status is read on every check, an error is only worth
+    /// logging once.
+    battery_error_logged: bool,
+    inhibitors: Vec<Inhibitor>,
+    process_inhibitors: Vec<ProcessInhibitor>,
+    next_process_check: Instant,
+}
+
+impl InhibitKind {
+    pub fn from_name(name: &str) -> Option<InhibitKind> {
+        match name {
+            "dim" => Some(InhibitKind::Dim),
+            "suspend" => Some(InhibitKind::Suspend),
+            "idle" => Some(InhibitKind::Idle),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InhibitKind::Dim => "dim",
+            InhibitKind::Suspend => "suspend",
+            InhibitKind::Idle => "idle",
+        }
+    }
+
+    fn dim(self) -> bool {
+        self != InhibitKind::Suspend
+    }
+
+    fn suspend(self) -> bool {
+        self != InhibitKind::Dim
+    }
+}
+
+impl IdleSystem for Daemon {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn charging(&self) -> Result<bool, Error> {
+        battery_status().map(|status| matches!(status, BatteryStatus::Charging | BatteryStatus::Full))
+    }
+
+    fn playback_running(&self) -> bool {
+        mixer::playback_running()
+    }
+
+    fn find_process(&self, rules: &[ProcessRule]) -> Option<ProcessMatch> {
+        processes::find(Path::new(processes::PROC_DIR), rules)
+    }
+
+    fn brightness(&self) -> Result<u32, Error> {
+        get_brightness()
+    }
+
+    fn set_brightness(&self, percent: u32) -> Result<(), Error> {
+        create_es_brightness_lock();
+        let result = set_brightness(percent);
+        remove_es_brightness_lock();
+        result
+    }
+
+    fn suspend(&self) -> Result<(), Error> {
+        suspend()
+    }
+}
+
+impl IdleTimer {
+    pub fn new() -> IdleTimer {
+        IdleTimer::with_system(Daemon)
+    }
+
+    /// Checks the idle deadlines and dims or suspends when they are due.
+    /// The settings are read on every check, so a reload takes effect
+    /// without losing track of the idle time.
+    pub fn check(&mut self) {
+        let settings = settings();
+        self.check_timeouts(&settings.config.auto_suspend, &settings.config.auto_dim);
+    }
+
+    /// Time left until the next idle deadline, to be used as poll timeout.
+    /// `None` means there is nothing to wait for.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let settings = settings();
+        self.time_left(&settings.config.auto_suspend, &settings.config.auto_dim)
+    }
+}
+
+impl<S: IdleSystem> IdleTimer<S> {
+    fn with_system(system: S) -> IdleTimer<S> {
+        let now = system.now();
+        IdleTimer {
+            system,
+            last_button_push: now,
+            last_charge: now,
+            last_playback: now,
+            next_playback_check: None,
+            auto_dim_active: false,
+            last_brightness: 0,
+            battery_error_logged: false,
+            inhibitors: Vec::new(),
+            process_inhibitors: Vec::new(),
+            next_process_check: now,
+        }
+    }
+
+    pub fn dimmed(&self) -> bool {
+        self.auto_dim_active
+    }
+
+    /// Time since the last button push.
+    pub fn idle_time(&self) -> Duration {
+        self.system.now().saturating_duration_since(self.last_button_push)
+    }
+
+    pub fn inhibitors(&self) -> &[Inhibitor] {
+        &self.inhibitors
+    }
+
+    /// The inhibitors, as control clients get them.
+    pub fn inhibitors_json(&self) -> Json {
+        Json::Array(
+            self.inhibitors
+                .iter()
+                .map(|inhibitor| {
+                    Json::object(vec![
+                        ("kind", Json::string(inhibitor.kind.name())),
+                        ("name", Json::string(&inhibitor.name)),
+                        ("reason", Json::string(&inhibitor.reason)),
+                        ("seconds", self.system.now().saturating_duration_since(inhibitor.since).as_secs().into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// The processes holding off a timeout, with the rule they matched.
+    pub fn process_inhibitors_json(&self) -> Json {
+        Json::Array(
+            self.process_inhibitors
+                .iter()
+                .map(|inhibitor| {
+                    Json::object(vec![
+                        ("kind", Json::string(inhibitor.kind.name())),
+                        ("rule", Json::String(inhibitor.process.rule.to_string())),
+                        ("process", Json::string(&inhibitor.process.name)),
+                        ("pid", inhibitor.process.pid.into()),
+                        ("seconds", self.system.now().saturating_duration_since(inhibitor.since).as_secs().into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// Adds an inhibitor, replacing the one of `owner` with the same name.
+    /// Inhibiting dimming while dimmed restores the brightness.
+    pub fn inhibit(&mut self, owner: Token, kind: InhibitKind, name: String, reason: String) {
+        self.inhibitors.retain(|inhibitor| inhibitor.owner != owner || inhibitor.name != name);
+        println!("{} inhibits {}: {}", name, kind.name(), reason);
+        event::emit(
+            "inhibited",
+            vec![("kind", Json::string(kind.name())), ("name", Json::string(&name)), ("reason", Json::string(&reason))],
+        );
+        self.inhibitors.push(Inhibitor {
+            owner,
+            kind,
+            name,
+            reason,
+            since: self.system.now(),
+        });
+        if kind.dim() && self.auto_dim_active {
+            self.undim();
+        }
+    }
+
+    /// Drops the inhibitors of `owner`, the one called `name` or all of
+    /// them, and returns how many there were. The timeouts then start over,
+    /// rather than dimming or suspending right away after a long inhibition.
+    pub fn release(&mut self, owner: Token, name: Option<&str>) -> usize {
+        let (released, kept) = std::mem::take(&mut self.inhibitors)
+            .into_iter()
+            .partition(|inhibitor| inhibitor.owner == owner && name.is_none_or(|name| inhibitor.name == name));
+        self.inhibitors = kept;
+
+        let released: Vec<Inhibitor> = released;
+        for inhibitor in released.iter() {
+            println!("{} no longer inhibits {}", inhibitor.name, inhibitor.kind.name());
+            event::emit(
+                "uninhibited",
+                vec![("kind", Json::string(inhibitor.kind.name())), ("name", Json::string(&inhibitor.name))],
+            );
+        }
+        if !released.is_empty() {
+            self.last_button_push = self.system.now();
+        }
+        released.len()
+    }
+
+    /// Records a button push and leaves the dimmed state if needed.
+    pub fn button_pushed(&mut self) {
+        self.last_button_push = self.system.now();
+        if self.auto_dim_active {
+            self.undim();
+        }
+    }
+
+    fn time_left(&self, auto_suspend: &AutoSuspendConfig, auto_dim: &AutoDimConfig) -> Option<Duration> {
+        let now = self.system.now();
+        let mut deadline: Option<Instant> = None;
+
+        if auto_suspend.enabled && !self.inhibited(InhibitKind::suspend) {
+            deadline = Some(self.deadline(
+                auto_suspend.timeout,
+                auto_suspend.stay_awake_while_charging,
+                auto_suspend.audio_activity,
+            ));
+        }
+
+        if auto_dim.enabled && !self.inhibited(InhibitKind::dim) {
+            let dim_deadline = if self.auto_dim_active {
+                if auto_dim.stay_awake_while_charging {
+                    Some(now + CHARGE_POLL_INTERVAL)
+                } else {
+                    None
+                }
+            } else {
+                Some(self.deadline(auto_dim.timeout, auto_dim.stay_awake_while_charging, auto_dim.audio_activity))
+            };
+
+            deadline = match (deadline, dim_deadline) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+        }
+
+        if !self.process_inhibitors.is_empty() {
+            deadline = Some(deadline.map_or(self.next_process_check, |d| d.min(self.next_process_check)));
+        }
+        if let Some(check) = self.next_playback_check {
+            deadline = Some(deadline.map_or(check, |d| d.min(check)));
+        }
+
+        deadline.map(|d| d.saturating_duration_since(now))
+    }
+
+    fn check_timeouts(&mut self, auto_suspend: &AutoSuspendConfig, auto_dim: &AutoDimConfig) {
+        // Auto-dim was turned off while dimmed
+        if self.auto_dim_active && !auto_dim.enabled {
+            self.undim();
+        }
+        if !auto_suspend.enabled && !auto_dim.enabled {
+            return;
+        }
+
+        let charging = match self.system.charging() {
+            Ok(charging) => {
+                self.battery_error_logged = false;
+                charging
+            }
+            Err(e) => {
+                if !self.battery_error_logged {
+                    println!("Can't read the battery status, assuming it doesn't charge: {}", e);
+                    self.battery_error_logged = true;
+                }
+                false
+            }
+        };
+        if charging {
+            self.last_charge = self.system.now();
+        }
+        self.check_processes(auto_suspend, auto_dim);
+        self.check_playback(auto_suspend, auto_dim);
+
+        if auto_suspend.enabled
+            && !self.inhibited(InhibitKind::suspend)
+            && self.timed_out(auto_suspend.timeout, auto_suspend.stay_awake_while_charging, auto_suspend.audio_activity)
+            && !self.playing(auto_suspend.audio_activity)
+            && !self.held_by_process(InhibitKind::Suspend, &auto_suspend.inhibit_processes)
+        {
+            if let Err(e) = self.system.suspend() {
+                println!("Auto-suspend failed: {}", e);
+            }
+            self.last_button_push = self.system.now();
+            self.last_charge = self.system.now();
+        }
+
+        if auto_dim.enabled && !self.inhibited(InhibitKind::dim) {
+            if self.auto_dim_active {
+                if auto_dim.stay_awake_while_charging && charging {
+                    self.undim();
+                }
+            } else if self.timed_out(auto_dim.timeout, auto_dim.stay_awake_while_charging, auto_dim.audio_activity)
+                && !self.playing(auto_dim.audio_activity)
+                && !self.held_by_process(InhibitKind::Dim, &auto_dim.inhibit_processes)
+            {
+                // Save current brightness and dim the screen, there is
+                // nothing to restore later if it can't be read
+                match self.system.brightness() {
+                    Ok(brightness) => {
+                        self.auto_dim_active = true;
+                        self.last_brightness = brightness;
+                        event::emit("dim_entered", vec![("brightness", auto_dim.brightness.into())]);
+                        if let Err(e) = self.system.set_brightness(auto_dim.brightness) {
+                            println!("Auto-dim failed: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        println!("Auto-dim skipped: {}", e);
+                        self.last_button_push = self.system.now();
+                    }
+                }
+            }
+        }
+    }
+
+    fn inhibited(&self, blocks: fn(InhibitKind) -> bool) -> bool {
+        self.inhibitors.iter().any(|inhibitor| blocks(inhibitor.kind))
+            || self.process_inhibitors.iter().any(|inhibitor| blocks(inhibitor.kind))
+    }
+
+    /// Looks for a process matching `rules` once the timeout of `kind` is
+    /// due, and lets it hold the timeout off if there is one.
+    fn held_by_process(&mut self, kind: InhibitKind, rules: &[ProcessRule]) -> bool {
+        let process = match self.system.find_process(rules) {
+            Some(process) => process,
+            None => return false,
+        };
+        let reason = format!("process rule {}", process.rule);
+        println!("{} ({}) inhibits {}: {}", process.name, process.pid, kind.name(), reason);
+        event::emit(
+            "inhibited",
+            vec![("kind", Json::string(kind.name())), ("name", Json::string(&process.name)), ("reason", Json::String(reason))],
+        );
+        self.process_inhibitors.push(ProcessInhibitor {
+            kind,
+            process,
+            since: self.system.now(),
+        });
+        self.next_process_check = self.system.now() + PROCESS_POLL_INTERVAL;
+        true
+    }
+
+    /// Looks again for the processes holding off a timeout, now and then.
+    /// Like a released inhibitor, one going away starts the timeouts over.
+    fn check_processes(&mut self, auto_suspend: &AutoSuspendConfig, auto_dim: &AutoDimConfig) {
+        if self.process_inhibitors.is_empty() || self.system.now() < self.next_process_check {
+            return;
+        }
+        self.next_process_check = self.system.now() + PROCESS_POLL_INTERVAL;
+
+        let mut released = false;
+        for inhibitor in std::mem::take(&mut self.process_inhibitors) {
+            let (enabled, rules) = match inhibitor.kind {
+                InhibitKind::Suspend => (auto_suspend.enabled, &auto_suspend.inhibit_processes),
+                _ => (auto_dim.enabled, &auto_dim.inhibit_processes),
+            };
+            match self.system.find_process(rules).filter(|_| enabled) {
+                Some(process) => self.process_inhibitors.push(ProcessInhibitor { process, ..inhibitor }),
+                None => {
+                    println!("{} no longer inhibits {}", inhibitor.process.name, inhibitor.kind.name());
+                    event::emit(
+                        "uninhibited",
+                        vec![("kind", Json::string(inhibitor.kind.name())), ("name", Json::string(&inhibitor.process.name))],
+                    );
+                    released = true;
+                }
+            }
+        }
+        if released {
+            self.last_button_push = self.system.now();
+        }
+    }
+
+    /// Whether sound plays, when it counts as activity. Playing sound
+    /// restarts the timeouts which count it, like a button push, but
+    /// leaves a dimmed screen dimmed.
+    fn playing(&mut self, audio_activity: bool) -> bool {
+        if !audio_activity || !self.system.playback_running() {
+            return false;
+        }
+        self.last_playback = self.system.now();
+        self.next_playback_check = Some(self.system.now() + PLAYBACK_POLL_INTERVAL);
+        true
+    }
+
+    /// Keeps looking at the playback while it goes on.
+    fn check_playback(&mut self, auto_suspend: &AutoSuspendConfig, auto_dim: &AutoDimConfig) {
+        match self.next_playback_check {
+            Some(check) if self.system.now() >= check => self.next_playback_check = None,
+            _ => return,
+        }
+        let audio_activity =
+            (auto_suspend.enabled && auto_suspend.audio_activity) || (auto_dim.enabled && auto_dim.audio_activity);
+        self.playing(audio_activity);
+    }
+
+    fn deadline(&self, timeout: Duration, stay_awake_while_charging: bool, audio_activity: bool) -> Instant {
+        let mut since = self.last_button_push;
+        if stay_awake_while_charging {
+            since = since.max(self.last_charge);
+        }
+        if audio_activity {
+            since = since.max(self.last_playback);
+        }
+        since + timeout
+    }
+
+    fn timed_out(&self, timeout: Duration, stay_awake_while_charging: bool, audio_activity: bool) -> bool {
+        self.system.now() >= self.deadline(timeout, stay_awake_while_charging, audio_activity)
+    }
+
+    fn undim(&mut self) {
+        // Restore previous brightness
+        self.auto_dim_active = false;
+        event::emit("dim_left", vec![("brightness", self.last_brightness.into())]);
+        if let Err(e) = self.system.set_brightness(self.last_brightness) {
+            println!("Can't restore the brightness: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    /// A clock moved by hand, with a log of what the timer did.
+    struct FakeSystem {
+        now: Cell<Instant>,
+        charging: Cell<bool>,
+        /// The name of the process any rule matches.
+        process: Cell<Option<&'static str>>,
+        brightness: Cell<u32>,
+        log: RefCell<Vec<String>>,
+    }
+
+    impl IdleSystem for FakeSystem {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+
+        fn charging(&self) -> Result<bool, Error> {
+            Ok(self.charging.get())
+        }
+
+        fn playback_running(&self) -> bool {
+            false
+        }
+
+        fn find_process(&self, rules: &[ProcessRule]) -> Option<ProcessMatch> {
+            Some(ProcessMatch {
+                rule: rules.first()?.clone(),
+                pid: 42,
+                name: self.process.get()?.to_string(),
+            })
+        }
+
+        fn brightness(&self) -> Result<u32, Error> {
+            Ok(self.brightness.get())
+        }
+
+        fn set_brightness(&self, percent: u32) -> Result<(), Error> {
+            self.log.borrow_mut().push(format!("brightness {}", percent));
+            self.brightness.set(percent);
+            Ok(())
+        }
+
+        fn suspend(&self) -> Result<(), Error> {
+            self.log.borrow_mut().push("suspend".to_string());
+            Ok(())
+        }
+    }
+
+    fn timer() -> IdleTimer<FakeSystem> {
+        IdleTimer::with_system(FakeSystem {
+            now: Cell::new(Instant::now()),
+            charging: Cell::new(false),
+            process: Cell::new(None),
+            brightness: Cell::new(60),
+            log: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn auto_suspend(enabled: bool) -> AutoSuspendConfig {
+        AutoSuspendConfig {
+            enabled,
+            timeout: Duration::from_secs(300),
+            stay_awake_while_charging: false,
+            inhibit_processes: Vec::new(),
+            audio_activity: false,
+        }
+    }
+
+    fn auto_dim(enabled: bool) -> AutoDimConfig {
+        AutoDimConfig {
+            enabled,
+            timeout: Duration::from_secs(60),
+            brightness: 10,
+            stay_awake_while_charging: false,
+            inhibit_processes: Vec::new(),
+            audio_activity: false,
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    impl IdleTimer<FakeSystem> {
+        fn wait(&mut self, time: Duration) {
+            self.system.now.set(self.system.now.get() + time);
+        }
+
+        fn log(&self) -> Vec<String> {
+            self.system.log.borrow().clone()
+        }
+    }
+
+    #[test]
+    fn next_timeout_is_the_closest_deadline() {
+        let mut timer = timer();
+        assert_eq!(timer.time_left(&auto_suspend(false), &auto_dim(false)), None);
+        assert_eq!(timer.time_left(&auto_suspend(true), &auto_dim(false)), Some(secs(300)));
+        assert_eq!(timer.time_left(&auto_suspend(true), &auto_dim(true)), Some(secs(60)));
+
+        timer.wait(secs(20));
+        assert_eq!(timer.time_left(&auto_suspend(true), &auto_dim(true)), Some(secs(40)));
+        timer.wait(secs(50));
+        assert_eq!(timer.time_left(&auto_suspend(true), &auto_dim(true)), Some(Duration::ZERO));
+        timer.button_pushed();
+        assert_eq!(timer.time_left(&auto_suspend(true), &auto_dim(true)), Some(secs(60)));
+    }
+
+    #[test]
+    fn dims_then_suspends() {
+        let mut timer = timer();
+        let (suspend, dim) = (auto_suspend(true), auto_dim(true));
+
+        timer.wait(secs(59));
+        timer.check_timeouts(&suspend, &dim);
+        assert!(!timer.dimmed());
+
+        timer.wait(secs(1));
+        timer.check_timeouts(&suspend, &dim);
+        assert!(timer.dimmed());
+        assert_eq!(timer.log(), vec!["brightness 10"]);
+        // Dimmed, only the suspend timeout is left
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(240)));
+
+        timer.wait(secs(240));
+        timer.check_timeouts(&suspend, &dim);
+        assert_eq!(timer.log(), vec!["brightness 10", "suspend"]);
+        // The timeouts start over after waking up
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(300)));
+        timer.check_timeouts(&suspend, &dim);
+        assert_eq!(timer.log().len(), 2);
+    }
+
+    #[test]
+    fn button_push_undims() {
+        let mut timer = timer();
+        let (suspend, dim) = (auto_suspend(true), auto_dim(true));
+        timer.wait(secs(60));
+        timer.check_timeouts(&suspend, &dim);
+        assert!(timer.dimmed());
+
+        timer.wait(secs(5));
+        timer.button_pushed();
+        assert!(!timer.dimmed());
+        assert_eq!(timer.log(), vec!["brightness 10", "brightness 60"]);
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(60)));
+        assert_eq!(timer.idle_time(), Duration::ZERO);
+
+        // A pushed button when not dimmed leaves the brightness alone
+        timer.button_pushed();
+        assert_eq!(timer.log().len(), 2);
+
+        // So does turning auto-dim off while dimmed
+        timer.wait(secs(60));
+        timer.check_timeouts(&suspend, &dim);
+        timer.check_timeouts(&suspend, &auto_dim(false));
+        assert!(!timer.dimmed());
+        assert_eq!(timer.log(), vec!["brightness 10", "brightness 60", "brightness 10", "brightness 60"]);
+    }
+
+    #[test]
+    fn charging_delays_the_deadline() {
+        let mut timer = timer();
+        let start = timer.system.now();
+        let dim = AutoDimConfig {
+            stay_awake_while_charging: true,
+            ..auto_dim(true)
+        };
+
+        timer.wait(secs(50));
+        timer.system.charging.set(true);
+        timer.check_timeouts(&auto_suspend(false), &dim);
+        timer.system.charging.set(false);
+        assert_eq!(timer.deadline(secs(60), true, false), start + secs(110));
+        assert_eq!(timer.deadline(secs(60), false, false), start + secs(60));
+
+        timer.wait(secs(20));
+        timer.check_timeouts(&auto_suspend(false), &dim);
+        assert!(!timer.dimmed());
+        timer.wait(secs(40));
+        timer.check_timeouts(&auto_suspend(false), &dim);
+        assert!(timer.dimmed());
+        // Looks at the battery now and then while dimmed
+        assert_eq!(timer.time_left(&auto_suspend(false), &dim), Some(CHARGE_POLL_INTERVAL));
+
+        timer.system.charging.set(true);
+        timer.check_timeouts(&auto_suspend(false), &dim);
+        assert!(!timer.dimmed());
+    }
+
+    #[test]
+    fn released_inhibitors_restart_the_timeouts() {
+        let mut timer = timer();
+        let (suspend, dim) = (auto_suspend(true), auto_dim(true));
+        timer.inhibit(Token(1), InhibitKind::Dim, "player".to_string(), "video".to_string());
+        timer.inhibit(Token(2), InhibitKind::Idle, "game".to_string(), String::new());
+        assert_eq!(timer.time_left(&suspend, &dim), None);
+
+        timer.wait(secs(600));
+        timer.check_timeouts(&suspend, &dim);
+        assert!(timer.log().is_empty());
+
+        assert_eq!(timer.release(Token(2), Some("other")), 0);
+        assert_eq!(timer.release(Token(2), None), 1);
+        // Only dimming is held off, suspend counts from the release
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(300)));
+        timer.check_timeouts(&suspend, &dim);
+        assert!(timer.log().is_empty());
+
+        assert_eq!(timer.release(Token(1), Some("player")), 1);
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(60)));
+        assert!(timer.inhibitors().is_empty());
+    }
+
+    #[test]
+    fn inhibiting_dimming_undims() {
+        let mut timer = timer();
+        timer.wait(secs(60));
+        timer.check_timeouts(&auto_suspend(false), &auto_dim(true));
+        assert!(timer.dimmed());
+
+        timer.inhibit(Token(1), InhibitKind::Suspend, "download".to_string(), String::new());
+        assert!(timer.dimmed());
+        timer.inhibit(Token(1), InhibitKind::Dim, "player".to_string(), String::new());
+        assert!(!timer.dimmed());
+        assert_eq!(timer.log(), vec!["brightness 10", "brightness 60"]);
+    }
+
+    #[test]
+    fn running_processes_hold_the_timeouts_off() {
+        let mut timer = timer();
+        let suspend = AutoSuspendConfig {
+            inhibit_processes: ProcessRule::parse_list("retroarch"),
+            ..auto_suspend(true)
+        };
+        let dim = auto_dim(false);
+
+        // Only looked for once the timeout is due
+        timer.system.process.set(Some("retroarch"));
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(300)));
+        timer.wait(secs(300));
+        timer.check_timeouts(&suspend, &dim);
+        assert!(timer.log().is_empty());
+        assert_eq!(timer.time_left(&suspend, &dim), Some(PROCESS_POLL_INTERVAL));
+
+        timer.wait(PROCESS_POLL_INTERVAL);
+        timer.check_timeouts(&suspend, &dim);
+        assert_eq!(timer.time_left(&suspend, &dim), Some(PROCESS_POLL_INTERVAL));
+
+        // Gone, the timeout starts over
+        timer.system.process.set(None);
+        timer.wait(PROCESS_POLL_INTERVAL);
+        timer.check_timeouts(&suspend, &dim);
+        assert!(timer.log().is_empty());
+        assert_eq!(timer.time_left(&suspend, &dim), Some(secs(300)));
+        timer.wait(secs(300));
+        timer.check_timeouts(&suspend, &dim);
+        assert_eq!(timer.log(), vec!["suspend"]);
+    }
+}
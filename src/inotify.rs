@@ -0,0 +1,92 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+
+/// Minimal non-blocking inotify instance, meant to be registered on the
+/// main `mio::Poll`.
+pub struct Inotify {
+    fd: RawFd,
+}
+
+pub struct InotifyEvent {
+    /// The watch, as returned by `add_watch`.
+    pub wd: i32,
+    pub mask: u32,
+    pub name: Option<OsString>,
+}
+
+impl Inotify {
+    pub fn new() -> io::Result<Inotify> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Inotify { fd })
+    }
+
+    pub fn add_watch(&self, path: &Path, mask: u32) -> io::Result<i32> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(wd)
+    }
+
+    /// Reads every queued event. The fd is edge triggered in mio, so this
+    /// keeps reading until the kernel has nothing left.
+    pub fn read_events(&self) -> io::Result<Vec<InotifyEvent>> {
+        let mut events = Vec::new();
+        let mut buffer = [0u8; 4096];
+        let header_size = mem::size_of::<libc::inotify_event>();
+
+        loop {
+            let len = unsafe { libc::read(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(events);
+                }
+                return Err(err);
+            }
+
+            let len = len as usize;
+            let mut offset = 0;
+            while offset + header_size <= len {
+                let raw: libc::inotify_event =
+                    unsafe { ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::inotify_event) };
+                let name_start = offset + header_size;
+                let name_end = (name_start + raw.len as usize).min(len);
+                let name = buffer[name_start..name_end].split(|b| *b == 0).next().unwrap_or(&[]);
+
+                events.push(InotifyEvent {
+                    wd: raw.wd,
+                    mask: raw.mask,
+                    name: if name.is_empty() { None } else { Some(OsStr::from_bytes(name).to_os_string()) },
+                });
+                offset = name_end;
+            }
+        }
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
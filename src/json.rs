@@ -0,0 +1,134 @@
+use std::fmt;
+
+/// Just enough JSON to answer control clients.
+#[derive(Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys keep the order they were given in.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn string(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+
+    /// The field called `key` of an object.
+    #[cfg(feature = "dbus-service")]
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Json {
+        Json::Number(i64::from(value))
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Json {
+        Json::Number(value.min(i64::MAX as u64) as i64)
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map(Into::into).unwrap_or(Json::Null)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(Json::string("say \"hi\"").to_string(), r#""say \"hi\"""#);
+        assert_eq!(Json::string("C:\\games").to_string(), r#""C:\\games""#);
+        assert_eq!(Json::string("a\nb\r\tc").to_string(), r#""a\nb\r\tc""#);
+        assert_eq!(Json::string("\u{1}\u{1f}\u{7f}é").to_string(), "\"\\u0001\\u001f\u{7f}é\"");
+    }
+
+    #[test]
+    fn objects_keep_their_order() {
+        let json = Json::object(vec![
+            ("volume", 40u32.into()),
+            ("muted", false.into()),
+            ("profile", Json::from(None::<String>)),
+            ("key \"q\"", Json::Array(vec![Json::string("x"), Json::Number(-1)])),
+        ]);
+        assert_eq!(json.to_string(), r#"{"volume":40,"muted":false,"profile":null,"key \"q\"":["x",-1]}"#);
+    }
+}
@@ -3,41 +3,85 @@ extern crate lazy_static;
 extern crate evdev_rs as evdev;
 extern crate mio;
 
-use evdev::enums::*;
+mod action;
+mod backlight;
+mod bindings;
+mod combo;
+mod config;
+mod control;
+#[cfg(feature = "dbus-service")]
+mod dbus_service;
+mod devices;
+mod error;
+mod event;
+mod gesture;
+mod grab;
+mod idle;
+mod inotify;
+mod json;
+mod mixer;
+mod perf;
+mod power;
+mod processes;
+mod profile;
+mod radio;
+mod repeat;
+mod signal;
+
+use evdev::enums::{EventCode, EventType};
 use evdev::*;
+use mio::event::Event;
 use mio::unix::SourceFd;
-use mio::{Events, Interest, Poll, Token};
-use props_rs::*;
-use std::collections::HashMap;
+use mio::{Events, Interest, Poll, Registry, Token};
 use std::fs;
-use std::fs::File;
-use std::io;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
 use std::thread;
-use std::time::{Duration, SystemTime};
-
-static PERF_MAX: EventCode = EventCode::EV_KEY(EV_KEY::BTN_TL2);
-static PERF_NORM: EventCode = EventCode::EV_KEY(EV_KEY::BTN_TL);
-static DARK_ON: EventCode = EventCode::EV_KEY(EV_KEY::BTN_DPAD_LEFT);
-static DARK_OFF: EventCode = EventCode::EV_KEY(EV_KEY::BTN_DPAD_RIGHT);
-static WIFI_ON: EventCode = EventCode::EV_KEY(EV_KEY::BTN_TR);
-static WIFI_OFF: EventCode = EventCode::EV_KEY(EV_KEY::BTN_TR2);
-static POWER_OFF: EventCode = EventCode::EV_KEY(EV_KEY::KEY_POWER);
-static MIN_POWERKEY_ELAPSED: Duration = Duration::from_secs(1);
-static DEVICE_FILE: &'static str = "/opt/.retrooz/device";
-static POWERKEY_CFG_FILE: &'static str = "/usr/local/etc/powerkey.conf";
-static OGAGE_CFG_FILE: &'static str = "/usr/local/etc/ogage.conf";
-static AUTO_SUSPEND_CFG_FILE: &'static str = "/usr/local/etc/auto_suspend.conf";
-static AUTO_DIM_CFG_FILE: &'static str = "/usr/local/etc/auto_dim.conf";
-static BATTERY_STATUS_FILE: &'static str = "/sys/class/power_supply/battery/status";
-
-enum PowerkeyActions {
-    Shutdown,
-    Suspend,
-    Disabled
-}
+use std::time::{Duration, Instant};
+
+use action::Action;
+use backlight::Backlight;
+use combo::ComboMatcher;
+use config::{Config, ConfigWatch, ControlConfig};
+use control::{Command, ControlServer};
+use devices::{DeviceFilter, InputDevices};
+use error::Error;
+use event::StateWatch;
+use gesture::GestureKey;
+use idle::IdleTimer;
+use json::Json;
+use mixer::Mixer;
+use perf::Performance;
+use power::Power;
+use profile::DeviceProfile;
+use radio::{RadioKind, Radios};
+use signal::SignalFd;
+
+static BATTERY_STATUS_FILE: &str = "/sys/class/power_supply/battery/status";
+static INPUT_DIR_TOKEN: Token = Token(0);
+static MIXER_TOKEN: Token = Token(1);
+static CONFIG_TOKEN: Token = Token(2);
+static SIGNAL_TOKEN: Token = Token(3);
+static CONTROL_TOKEN: Token = Token(4);
+#[cfg(feature = "dbus-service")]
+static DBUS_TOKEN: Token = Token(5);
+#[cfg(feature = "dbus-service")]
+static FIRST_INHIBIT_PIPE_TOKEN: Token = Token(usize::MAX / 4);
+static FIRST_DEVICE_TOKEN: Token = Token(100);
+static FIRST_CLIENT_TOKEN: Token = Token(usize::MAX / 2);
+static FIRST_SUBSCRIBER_TOKEN: Token = Token(usize::MAX / 4 * 3);
+
+// How long to wait for more configuration files to change before reloading
+static RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+// Brightness to restore when screen_toggle turns the screen back on, 0
+// while the screen is on
+static SCREEN_OFF_BRIGHTNESS: AtomicU32 = AtomicU32::new(0);
+
+// Volume ogage set last, to tell changes made by other programs apart
+static LAST_VOLUME: AtomicU32 = AtomicU32::new(u32::MAX);
 
 enum BatteryStatus {
     Unknown,
@@ -47,902 +91,971 @@ enum BatteryStatus {
     Full,
 }
 
-lazy_static! {
-    static ref DEVICE: &'static str = {
-        if Path::new(DEVICE_FILE).exists() {
-            let lines = fs::read_to_string(DEVICE_FILE).expect(&("Can't read file '".to_owned() + DEVICE_FILE + "'.")).trim_end_matches(&['\r', '\n'][..]).to_string();
-            if !lines.is_empty() {
-                return Box::leak(lines.into_boxed_str());
-            }
-        }
-
-        "rgb10maxtop"
-    };
-
-    static ref IS_OGA1: bool = {
-        if *DEVICE == "oga1" {
-            return true;
-        }
-        // OGS, OGA, RGB10 MAX/MAX2
-        false
-    };
-
-    static ref HOTKEY: EventCode = {
-        let device_str = DEVICE.to_string();
-        if device_str.starts_with("rgb10max") {
-            if device_str.ends_with("top") {
-                return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY4);
-            }
-            else { // native
-                return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY2);
-            }
-        }
-
-        // OGS, OGA and OGA 1.1
-        EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY6)
-    };
-
-    static ref BRIGHT_UP: EventCode = {
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY5);
-        }
-
-        // OGS, OGA, RGB10 MAX/MAX2
-        EventCode::EV_KEY(EV_KEY::BTN_DPAD_UP)
-    };
-
-    static ref BRIGHT_DOWN: EventCode = {
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY4);
-        }
-
-        // OGS, OGA, RGB10 MAX/MAX2
-        EventCode::EV_KEY(EV_KEY::BTN_DPAD_DOWN)
-    };
-
-    static ref VOL_UP: EventCode = {
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY3);
-        }
-
-        // OGS, OGA, RGB10 MAX/MAX2
-        EventCode::EV_KEY(EV_KEY::BTN_NORTH)
-    };
-
-    static ref VOL_DOWN: EventCode = {
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY2);
-        }
-
-        // OGS, OGA, RGB10 MAX/MAX2
-        EventCode::EV_KEY(EV_KEY::BTN_SOUTH)
-    };
-
-    static ref MUTE: EventCode = {
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_DPAD_DOWN);
-        }
-
-        // OGS, OGA, RGB10 MAX/MAX2
-        EventCode::EV_KEY(EV_KEY::BTN_WEST)
-    };
-
-    static ref VOL_NORM: EventCode = {
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_DPAD_UP);
+impl BatteryStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            BatteryStatus::Unknown => "unknown",
+            BatteryStatus::Charging => "charging",
+            BatteryStatus::Discharging => "discharging",
+            BatteryStatus::NotCharging => "not charging",
+            BatteryStatus::Full => "full",
         }
+    }
+}
 
-        // OGS, OGA, RGB10 MAX/MAX2
-        EventCode::EV_KEY(EV_KEY::BTN_EAST)
-    };
-
-    static ref SUSPEND: EventCode = {
-        let device_str = DEVICE.to_string();
-        if *IS_OGA1 {
-            return EventCode::EV_KEY(EV_KEY::BTN_NORTH);
-        }
-        else if device_str.starts_with("rgb10max") && device_str.ends_with("native") {
-            return EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY4);
-        }
-
-        // OGS, OGA, RGB10 MAX/MAX2 Top
-        EventCode::EV_KEY(EV_KEY::BTN_TRIGGER_HAPPY2)
-    };
-
-    static ref POWERKEY_PROPERTIES: HashMap<String, String> = {
-        println!("\nPOWERKEY_PROPERTIES:");
-        if Path::new(POWERKEY_CFG_FILE).exists() {
-            let lines = fs::read_to_string(POWERKEY_CFG_FILE).expect(&("Can't read file '".to_owned() + POWERKEY_CFG_FILE + "'."));
-            let parsed = parse(lines.as_bytes()).expect(&("Can't parse properties of '".to_owned() + POWERKEY_CFG_FILE + "'."));
+lazy_static! {
+    static ref SETTINGS: RwLock<Arc<Settings>> = RwLock::new(Arc::new(Settings::load(None)));
+}
 
-            let map_properties = to_map(parsed);
+/// The configuration, the device profile it picks and the backends it
+/// names. A reload replaces all of them at once.
+struct Settings {
+    config: Config,
+    profile: DeviceProfile,
+    backlight: Arc<dyn Backlight>,
+    mixer: Arc<dyn Mixer>,
+    radios: Arc<dyn Radios>,
+    power: Arc<dyn Power>,
+    performance: Performance,
+}
 
-            for (key, value) in map_properties.iter() {
-                println!("\t{} / {}", key, value);
-            }
-            println!("\n");
-            return map_properties;
+impl Settings {
+    /// Reads the configuration. The backends of `previous` are kept when
+    /// their settings didn't change.
+    fn load(previous: Option<&Settings>) -> Settings {
+        let config = Config::load();
+        let mut profile = DeviceProfile::detect(config.device.as_deref());
+        profile.apply_overrides(config.input.bindings.clone());
+
+        let keep = |same: &dyn Fn(&Config) -> bool| previous.filter(|previous| same(&previous.config));
+        let backlight = match keep(&|old| old.brightness.backend == config.brightness.backend) {
+            Some(previous) => previous.backlight.clone(),
+            None => Arc::from(backlight::select(config.brightness.backend.as_deref())),
+        };
+        let mixer = match keep(&|old| {
+            old.volume.backend == config.volume.backend
+                && old.volume.card == config.volume.card
+                && old.volume.control == config.volume.control
+        }) {
+            Some(previous) => previous.mixer.clone(),
+            None => Arc::from(mixer::select(config.volume.backend.as_deref(), config.volume.card, &config.volume.control)),
+        };
+        let radios = match keep(&|old| old.radios.backend == config.radios.backend) {
+            Some(previous) => previous.radios.clone(),
+            None => Arc::from(radio::select(config.radios.backend.as_deref())),
+        };
+        let power = match keep(&|old| old.power.backend == config.power.backend) {
+            Some(previous) => previous.power.clone(),
+            None => Arc::from(power::select(config.power.backend.as_deref())),
+        };
+        let performance = Performance::new(
+            config.performance.profiles.clone(),
+            Path::new(perf::CPUFREQ_DIR),
+            Path::new(perf::DEVFREQ_DIR),
+        );
+        if let Some(previous) = previous {
+            performance.inherit_active(&previous.performance);
         }
 
-        HashMap::new()
-    };
-
-    static ref IS_DOUBLE_PUSH_POWERKEY_ACTIVE: bool = {
-        if !POWERKEY_PROPERTIES.is_empty() {
-            match POWERKEY_PROPERTIES.get("two_push_shutdown") {
-                Some(x) => {
-                    if x == "enabled" {
-                        return true;
-                    }
-                },
-                _ => ()
-            };
+        Settings {
+            config,
+            profile,
+            backlight,
+            mixer,
+            radios,
+            power,
+            performance,
         }
+    }
+}
 
-        false
-    };
+/// The active settings, which stay valid for the caller across a reload.
+fn settings() -> Arc<Settings> {
+    SETTINGS.read().unwrap().clone()
+}
 
-    static ref MAX_POWERKEY_INTERVAL_TIME: Duration = {
-        if !POWERKEY_PROPERTIES.is_empty() {
-            match POWERKEY_PROPERTIES.get("max_interval_time") {
-                Some(x) => return Duration::from_secs(x.parse::<u64>().unwrap() + MIN_POWERKEY_ELAPSED.as_secs()),
-                None => return Duration::from_secs(2),
-            };
-        }
+/// Re-reads the configuration and makes it the active one.
+fn reload() -> Arc<Settings> {
+    let settings = Arc::new(Settings::load(Some(&settings())));
+    *SETTINGS.write().unwrap() = settings.clone();
+    settings
+}
 
-        Duration::from_secs(2)
-    };
+fn get_brightness() -> Result<u32, Error> {
+    let settings = settings();
+    settings.backlight.brightness().map_err(|e| Error::backend(settings.backlight.describe(), "read brightness", e))
+}
 
-    static ref POWERKEY_ACTION: PowerkeyActions = {
-        if !POWERKEY_PROPERTIES.is_empty() {
-            match POWERKEY_PROPERTIES.get("action") {
-                Some(x) => {
-                    if x == "suspend" {
-                        return PowerkeyActions::Suspend;
-                    }
-                    else if x == "disabled" {
-                        return PowerkeyActions::Disabled;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn set_brightness(brightness: u32) -> Result<(), Error> {
+    //println!("Set brightness level: {}%", brightness);
+    let settings = settings();
+    settings
+        .backlight
+        .set_brightness(brightness)
+        .map_err(|e| Error::backend(settings.backlight.describe(), "set brightness", e))
+}
 
-        PowerkeyActions::Shutdown
-    };
+fn blinkon() -> Result<(), Error> {
+    let current = get_brightness()?;
+    create_es_brightness_lock();
+    set_brightness(0)?;
+    thread::sleep(Duration::from_millis(200));
+    create_es_brightness_lock();
+    set_brightness(100)?;
+    thread::sleep(Duration::from_millis(200));
+    create_es_brightness_lock();
+    set_brightness(0)?;
+    thread::sleep(Duration::from_millis(200));
+    create_es_brightness_lock();
+    set_brightness(current)?;
+    remove_es_brightness_lock();
+    Ok(())
+}
 
-    static ref AUTO_SUSPEND_PROPERTIES: HashMap<String, String> = {
-        println!("\nAUTO_SUSPEND_PROPERTIES:");
-        if Path::new(AUTO_SUSPEND_CFG_FILE).exists() {
-            let lines = fs::read_to_string(AUTO_SUSPEND_CFG_FILE).expect(&("Can't read file '".to_owned() + AUTO_SUSPEND_CFG_FILE + "'."));
-            let parsed = parse(lines.as_bytes()).expect(&("Can't parse properties of '".to_owned() + AUTO_SUSPEND_CFG_FILE + "'."));
+fn blinkoff() -> Result<(), Error> {
+    let current = get_brightness()?;
+    create_es_brightness_lock();
+    set_brightness(0)?;
+    thread::sleep(Duration::from_millis(300));
+    create_es_brightness_lock();
+    set_brightness(current)?;
+    remove_es_brightness_lock();
+    Ok(())
+}
 
-            let map_properties = to_map(parsed);
+fn get_volume() -> Result<u32, Error> {
+    let settings = settings();
+    settings.mixer.volume().map_err(|e| Error::backend(settings.mixer.describe(), "read volume", e))
+}
 
-            for (key, value) in map_properties.iter() {
-                println!("\t{} / {}", key, value);
-            }
-            println!("\n");
-            return map_properties;
-        }
+fn set_volume(volume: u32) -> Result<(), Error> {
+    //println!("Set volume level: {}%", volume);
+    let settings = settings();
+    settings
+        .mixer
+        .set_volume(volume)
+        .and_then(|_| settings.mixer.set_muted(volume == 0))
+        .map_err(|e| Error::backend(settings.mixer.describe(), "set volume", e))?;
+
+    // Read back, the control may not have a step for every percent
+    if settings.mixer.notify_fd().is_some() {
+        LAST_VOLUME.store(get_volume()?, Ordering::SeqCst);
+    }
+    Ok(())
+}
 
-        HashMap::new()
-    };
+fn inc_brightness() -> Result<(), Error> {
+    let settings = settings();
+    let mut brightness = get_brightness()?;
+    if brightness < settings.config.brightness.step {
+        brightness = settings.config.brightness.step;
+    }
+    else {
+        brightness += settings.config.brightness.step;
+    }
+    set_brightness(brightness)
+}
 
-    static ref AUTO_SUSPEND_ENABLED: bool = {
-        if !AUTO_SUSPEND_PROPERTIES.is_empty() {
-            match AUTO_SUSPEND_PROPERTIES.get("auto_suspend_time") {
-                Some(x) => {
-                    if x == "enabled" {
-                        return true;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn dec_brightness() -> Result<(), Error> {
+    let settings = settings();
+    let mut brightness = get_brightness()?;
+    if brightness <= settings.config.brightness.step {
+        brightness = 1;
+    }
+    else {
+        brightness -= settings.config.brightness.step;
+    }
+    set_brightness(brightness)
+}
 
-        false
-    };
+fn inc_volume() -> Result<(), Error> {
+    let settings = settings();
+    let mut volume = get_volume()?;
+    if volume < settings.config.volume.step {
+        volume = settings.config.volume.step;
+    }
+    else {
+        volume += settings.config.volume.step;
+    }
+    set_volume(volume)
+}
 
-    // timeout in minutes
-    static ref AUTO_SUSPEND_TIMEOUT: Duration = {
-        if !AUTO_SUSPEND_PROPERTIES.is_empty() {
-            match AUTO_SUSPEND_PROPERTIES.get("auto_suspend_timeout") {
-                Some(x) => return Duration::from_secs(x.parse::<u64>().unwrap() * 60),
-                _ => ()
-            };
-        }
+fn dec_volume() -> Result<(), Error> {
+    let settings = settings();
+    let mut volume = get_volume()?;
+    if volume < settings.config.volume.step {
+        volume = 0;
+    }
+    else {
+        volume -= settings.config.volume.step;
+    }
+    set_volume(volume)
+}
 
-        Duration::from_secs(300)
-    };
+fn mute_volume() -> Result<(), Error> {
+    let settings = settings();
+    settings.mixer.set_muted(true).map_err(|e| Error::backend(settings.mixer.describe(), "mute", e))
+}
 
-    static ref AUTO_SUSPEND_STAY_AWAKE_WHILE_CHARGING: bool = {
-        if !AUTO_SUSPEND_PROPERTIES.is_empty() {
-            match AUTO_SUSPEND_PROPERTIES.get("auto_suspend_stay_awake_while_charging") {
-                Some(x) => {
-                    if x == "enabled" {
-                        return true;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn unmute_volume() -> Result<(), Error> {
+    let settings = settings();
+    settings.mixer.set_muted(false).map_err(|e| Error::backend(settings.mixer.describe(), "unmute", e))
+}
 
-        false
-    };
+fn norm_volume() -> Result<(), Error> {
+    set_volume(75)
+}
 
-    static ref AUTO_DIM_PROPERTIES: HashMap<String, String> = {
-        println!("\nAUTO_DIM_PROPERTIES:");
-        if Path::new(AUTO_DIM_CFG_FILE).exists() {
-            let lines = fs::read_to_string(AUTO_DIM_CFG_FILE).expect(&("Can't read file '".to_owned() + AUTO_DIM_CFG_FILE + "'."));
-            let parsed = parse(lines.as_bytes()).expect(&("Can't parse properties of '".to_owned() + AUTO_DIM_CFG_FILE + "'."));
+/// Blinks twice for the `max` profile and once for the others.
+fn set_perf_profile(name: &str) -> Result<(), Error> {
+    let settings = settings();
+    settings.performance.apply(name)?;
+    if name == "max" {
+        blinkon()
+    } else {
+        blinkoff()
+    }
+}
 
-            let map_properties = to_map(parsed);
+fn perf_max() -> Result<(), Error> {
+    set_perf_profile("max")
+}
 
-            for (key, value) in map_properties.iter() {
-                println!("\t{} / {}", key, value);
-            }
-            println!("\n");
-            return map_properties;
-        }
+fn perf_norm() -> Result<(), Error> {
+    set_perf_profile("norm")
+}
 
-        HashMap::new()
-    };
-    
-    static ref AUTO_DIM_STAY_AWAKE_WHILE_CHARGING: bool = {
-        if !AUTO_DIM_PROPERTIES.is_empty() {
-            match AUTO_DIM_PROPERTIES.get("auto_dim_stay_awake_while_charging") {
-                Some(x) => {
-                    if x == "enabled" {
-                        return true;
-                    }
-                },
-                _ => ()
-            };
+fn perf_cycle() -> Result<(), Error> {
+    let settings = settings();
+    match settings.performance.cycle()? {
+        Some("max") => blinkon(),
+        Some(_) => blinkoff(),
+        None => {
+            println!("No performance profile to cycle through");
+            Ok(())
         }
+    }
+}
 
-        false
-    };
-
-    static ref AUTO_DIM_ENABLED: bool = {
-        if !AUTO_DIM_PROPERTIES.is_empty() {
-            match AUTO_DIM_PROPERTIES.get("auto_dim_time") {
-                Some(x) => {
-                    if x == "enabled" {
-                        return true;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn dark_on() -> Result<(), Error> {
+    set_brightness(10)
+}
 
-        false
-    };
+fn dark_off() -> Result<(), Error> {
+    set_brightness(50)
+}
 
-    // timeout in minutes
-    static ref AUTO_DIM_TIMEOUT: Duration = {
-        if !AUTO_DIM_PROPERTIES.is_empty() {
-            match AUTO_DIM_PROPERTIES.get("auto_dim_timeout") {
-                Some(x) => return Duration::from_secs(x.parse::<u64>().unwrap() * 60),
-                _ => ()
-            };
+fn screen_toggle() -> Result<(), Error> {
+    match SCREEN_OFF_BRIGHTNESS.swap(0, Ordering::SeqCst) {
+        0 => {
+            SCREEN_OFF_BRIGHTNESS.store(get_brightness()?.max(1), Ordering::SeqCst);
+            set_brightness(0)
         }
+        brightness => set_brightness(brightness),
+    }
+}
 
-        Duration::from_secs(300)
-    };
+/// Switches the radio and blinks the screen after what it ended up doing:
+/// a radio kept off by a hardware switch blinks as off.
+fn set_radio(kind: RadioKind, enabled: bool) -> Result<(), Error> {
+    let settings = settings();
+    if let Err(e) = settings.radios.set_enabled(kind, enabled) {
+        println!("{}", Error::backend(settings.radios.describe(), "switch the radio", e));
+    }
 
-    // brightness level
-    static ref AUTO_DIM_BRIGHTNESS: u32 = {
-        if !AUTO_DIM_PROPERTIES.is_empty() {
-            match AUTO_DIM_PROPERTIES.get("auto_dim_brightness") {
-                Some(x) => return x.parse::<u32>().unwrap(),
-                _ => ()
-            };
+    match settings.radios.enabled(kind) {
+        Ok(Some(true)) => {
+            println!("{} on", kind);
+            blinkon()
         }
-
-        10
-    };
-
-    static ref OGAGE_PROPERTIES: HashMap<String, String> = {
-        println!("\nOGAGE PROPERTIES:");
-        if Path::new(OGAGE_CFG_FILE).exists() {
-            let lines = fs::read_to_string(OGAGE_CFG_FILE).expect(&("Can't read file '".to_owned() + OGAGE_CFG_FILE + "'."));
-            let parsed = parse(lines.as_bytes()).expect(&("Can't parse properties of '".to_owned() + OGAGE_CFG_FILE + "'."));
-
-            let map_properties = to_map(parsed);
-
-            for (key, value) in map_properties.iter() {
-                println!("\t{} / {}", key, value);
-            }
-            println!("\n");
-            return map_properties;
+        Ok(Some(false)) => {
+            println!("{} off", kind);
+            blinkoff()
         }
+        Ok(None) => Err(Error::Unknown { what: "radio", name: kind.to_string() }),
+        Err(e) => Err(Error::backend(settings.radios.describe(), "read the radio state", e)),
+    }
+}
 
-        HashMap::new()
-    };
-
-    static ref ALLOW_BRIGHTNESS: bool = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("brightness") {
-                Some(x) => {
-                    if x == "disabled" {
-                        return false;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn wifi_on() -> Result<(), Error> {
+    set_radio(RadioKind::Wifi, true)
+}
 
-        true
-    };
+fn wifi_off() -> Result<(), Error> {
+    set_radio(RadioKind::Wifi, false)
+}
 
-    static ref BRIGHTNESS_STEP: u32 = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("brightness_step") {
-                Some(x) => return x.parse::<u32>().unwrap(),
-                _ => ()
-            };
-        }
+fn bluetooth_on() -> Result<(), Error> {
+    set_radio(RadioKind::Bluetooth, true)
+}
 
-        1
-    };
+fn bluetooth_off() -> Result<(), Error> {
+    set_radio(RadioKind::Bluetooth, false)
+}
 
-    static ref ALLOW_VOLUME: bool = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("volume") {
-                Some(x) => {
-                    if x == "disabled" {
-                        return false;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn suspend() -> Result<(), Error> {
+    // Written out before the system goes down
+    event::emit("suspend", Vec::new());
+    let settings = settings();
+    settings.power.suspend().map_err(|e| Error::backend(settings.power.describe(), "suspend", e))
+}
 
-        true
-    };
+fn power_off() -> Result<(), Error> {
+    let settings = settings();
+    settings.power.power_off().map_err(|e| Error::backend(settings.power.describe(), "power off", e))
+}
 
-    static ref VOLUME_STEP: u32 = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("volume_step") {
-                Some(x) => return x.parse::<u32>().unwrap(),
-                _ => ()
-            };
-        }
+fn battery_status() -> Result<BatteryStatus, Error> {
+    let status_str =
+        fs::read_to_string(BATTERY_STATUS_FILE).map_err(|e| Error::file(BATTERY_STATUS_FILE, e))?;
 
-        1
-    };
+    match status_str.as_str().trim() {
+        "Unknown" => Ok(BatteryStatus::Unknown),
+        "Charging" => Ok(BatteryStatus::Charging),
+        "Discharging" => Ok(BatteryStatus::Discharging),
+        "Not charging" => Ok(BatteryStatus::NotCharging),
+        "Full" => Ok(BatteryStatus::Full),
+        value => Err(Error::UnexpectedValue {
+            path: BATTERY_STATUS_FILE.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
 
-    static ref ALLOW_WIFI: bool = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("wifi") {
-                Some(x) => {
-                    if x == "disabled" {
-                        return false;
-                    }
-                },
-                _ => ()
-            };
+// The lock only keeps EmulationStation from fighting over the brightness,
+// failing to handle it is no reason not to change the brightness
+fn create_es_brightness_lock() {
+    let settings = settings();
+    if !Path::new(&settings.config.brightness.es_lock_file).exists() {
+        if let Err(e) = std::fs::File::create(&settings.config.brightness.es_lock_file) {
+            println!("{}", Error::file(&settings.config.brightness.es_lock_file, e));
         }
+    }
+}
 
-        true
-    };
-
-    static ref ALLOW_PERFORMANCE: bool = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("performance") {
-                Some(x) => {
-                    if x == "disabled" {
-                        return false;
-                    }
-                },
-                _ => ()
-            };
+fn remove_es_brightness_lock() {
+    let settings = settings();
+    if Path::new(&settings.config.brightness.es_lock_file).exists() {
+        thread::sleep(Duration::from_millis(200));
+        if let Err(e) = fs::remove_file(&settings.config.brightness.es_lock_file) {
+            println!("{}", Error::file(&settings.config.brightness.es_lock_file, e));
         }
+    }
+}
 
-        true
-    };
-
-    static ref ALLOW_SUSPEND: bool = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("suspend") {
-                Some(x) => {
-                    if x == "disabled" {
-                        return false;
-                    }
-                },
-                _ => ()
-            };
-        }
+fn device_filter(settings: &Settings, gestures: &[GestureKey]) -> DeviceFilter {
+    let mut codes = settings.profile.codes();
+    codes.extend(gestures.iter().map(|gesture| gesture.code.clone()));
 
-        true
-    };
+    DeviceFilter {
+        codes,
+        include: settings.config.input.devices_include.clone(),
+        exclude: settings.config.input.devices_exclude.clone(),
+    }
+}
 
-    static ref ES_BRIGTHNESS_LOCK_FILE: &'static str = {
-        if !OGAGE_PROPERTIES.is_empty() {
-            match OGAGE_PROPERTIES.get("es_brigthness_lock_file") {
-                Some(x) => {
-                    return x;
-                },
-                _ => ()
-            };
+/// Whether ogage.conf lets buttons trigger the action.
+fn allowed(action: Action) -> bool {
+    let settings = settings();
+    match action {
+        Action::BrightUp | Action::BrightDown | Action::DarkOn | Action::DarkOff | Action::ScreenToggle => {
+            settings.config.brightness.allowed
         }
+        Action::VolUp | Action::VolDown | Action::Mute | Action::VolNorm => settings.config.volume.allowed,
+        Action::PerfMax | Action::PerfNorm | Action::PerfCycle => settings.config.performance.allowed,
+        Action::WifiOn | Action::WifiOff => settings.config.radios.allow_wifi,
+        Action::BluetoothOn | Action::BluetoothOff => settings.config.radios.allow_bluetooth,
+        Action::Suspend => settings.config.power.allow_suspend,
+        Action::PowerOff => true,
+    }
+}
 
-        "/home/odroid/.emulationstation/brightness.lock"
+fn perform(action: Action) -> Result<(), Error> {
+    let value = match action {
+        Action::BrightUp | Action::BrightDown | Action::DarkOn | Action::DarkOff | Action::ScreenToggle => brightness_value,
+        Action::VolUp | Action::VolDown | Action::Mute | Action::VolNorm => volume_value,
+        Action::PerfMax | Action::PerfNorm | Action::PerfCycle => perf_value,
+        Action::WifiOn | Action::WifiOff => wifi_value,
+        Action::BluetoothOn | Action::BluetoothOff => bluetooth_value,
+        Action::Suspend | Action::PowerOff => || Json::Null,
     };
+    reported(action.name(), value, || perform_action(action))
 }
 
-fn get_brightness() -> u32 {
-    let output = Command::new("brightnessctl")
-        .arg("-m")
-        .stdout(Stdio::piped())
-        .output()
-        .expect("Failed to execute brightnessctl");
-    let brightness_str =
-        String::from_utf8(output.stdout).expect("Failed to convert stdout to string");
-
-    let brightness_vector: Vec<&str> = brightness_str.split(&[',', '%'][..]).collect();
+fn perform_action(action: Action) -> Result<(), Error> {
+    match action {
+        Action::BrightUp => inc_brightness(),
+        Action::BrightDown => dec_brightness(),
+        Action::DarkOn => dark_on(),
+        Action::DarkOff => dark_off(),
+        Action::ScreenToggle => screen_toggle(),
+        Action::VolUp => inc_volume(),
+        Action::VolDown => dec_volume(),
+        Action::Mute => mute_volume(),
+        Action::VolNorm => norm_volume(),
+        Action::PerfMax => perf_max(),
+        Action::PerfNorm => perf_norm(),
+        Action::PerfCycle => perf_cycle(),
+        Action::WifiOn => wifi_on(),
+        Action::WifiOff => wifi_off(),
+        Action::BluetoothOn => bluetooth_on(),
+        Action::BluetoothOff => bluetooth_off(),
+        Action::Suspend => suspend(),
+        Action::PowerOff => power_off(),
+    }
+}
 
-    if brightness_vector.len() > 3 {
-        //println!("Get volume level: {}%", brightness_vector[3]);
-        return brightness_vector[3]
-                .trim()
-                .parse::<u32>()
-                .expect("Failed to parse brightness string")
+/// Makes a change and tells the subscribers about it, with what `value`
+/// reads before and after.
+fn reported(name: &str, value: fn() -> Json, change: impl FnOnce() -> Result<(), Error>) -> Result<(), Error> {
+    if !event::subscribed() {
+        return change();
     }
 
-    50
+    let old = value();
+    let result = change();
+    event::emit(
+        "action",
+        vec![("action", Json::string(name)), ("old", old), ("new", value()), ("ok", result.is_ok().into())],
+    );
+    result
 }
 
-fn set_brightness(brightness: u32) {
-    let brightness_str = brightness.to_string() + "%";
-    //println!("Set brightness level: {}", brightness_str);
-    Command::new("brightnessctl")
-        .args(&["s", &brightness_str])
-        .output()
-        .expect("Failed to execute brightnessctl");
+fn volume_value() -> Json {
+    get_volume().ok().into()
 }
 
-fn blinkon() {
-    let current = get_brightness();
-    create_es_brightness_lock();
-    set_brightness(0);
-    thread::sleep(Duration::from_millis(200));
-    create_es_brightness_lock();
-    set_brightness(100);
-    thread::sleep(Duration::from_millis(200));
-    create_es_brightness_lock();
-    set_brightness(0);
-    thread::sleep(Duration::from_millis(200));
-    create_es_brightness_lock();
-    set_brightness(current);
-    remove_es_brightness_lock();
+fn brightness_value() -> Json {
+    get_brightness().ok().into()
 }
 
-fn blinkoff() {
-    let current = get_brightness();
-    create_es_brightness_lock();
-    set_brightness(0);
-    thread::sleep(Duration::from_millis(300));
-    create_es_brightness_lock();
-    set_brightness(current);
-    remove_es_brightness_lock();
+fn perf_value() -> Json {
+    settings().performance.active().into()
 }
 
-fn get_volume() -> u32 {
-    let output = Command::new("amixer")
-        .args(&["sget", "Playback"])
-        .stdout(Stdio::piped())
-        .output()
-        .expect("Failed to execute amixer");
-
-    let lines_str =
-        String::from_utf8(output.stdout).expect("Failed to convert stdout to string");
-
-    let last_line = lines_str
-        .lines()
-        .last()
-        .expect("could not get volume info");
+fn wifi_value() -> Json {
+    settings().radios.enabled(RadioKind::Wifi).ok().flatten().into()
+}
 
-    let last = last_line
-        .split_whitespace()
-        .filter(|x| x.starts_with('[') && !x.contains("dB"))
-        .map(|s| s.trim_matches(&['[', ']', '%'] as &[_]))
-        .collect::<Vec<&str>>();
+fn bluetooth_value() -> Json {
+    settings().radios.enabled(RadioKind::Bluetooth).ok().flatten().into()
+}
 
-    if last.len() > 0 {
-        //println!("Get volume level: {}%", last[0]);
-        return last[0]
-                .trim()
-                .parse::<u32>()
-                .expect("Failed to parse volume  string")
+fn run_action(action: Action) {
+    if allowed(action) {
+        run_powerkey_action(action);
     }
-
-    50
 }
 
-fn set_volume(volume: u32) {
-    let volume_str = volume.to_string() + "%";
-    //println!("Set volume level: {}", volume_str);
-    Command::new("amixer")
-        .args(&["sset", "Playback", &volume_str])
-        .output()
-        .expect("Failed to execute amixer");
+/// Power key gestures are configured in powerkey.conf and don't obey the
+/// switches of ogage.conf.
+fn run_powerkey_action(action: Action) {
+    if let Err(e) = perform(action) {
+        println!("Action {} failed: {}", action, e);
+    }
 }
 
-fn inc_brightness() {
-    let mut brightness = get_brightness();
-    if brightness < *BRIGHTNESS_STEP {
-        brightness = *BRIGHTNESS_STEP;
+fn process_event(
+    settings: &Settings,
+    combos: &mut ComboMatcher,
+    powerkey: &mut GestureKey,
+    gestures: &mut [GestureKey],
+    token: Token,
+    ev: &InputEvent,
+) {
+    /*
+    println!("Event: time {}.{} type {} code {} value {} hotkey {}",
+             ev.time.tv_sec,
+             ev.time.tv_usec,
+             ev.event_type,
+             ev.event_code,
+             ev.value,
+             combos.is_down(&settings.profile.hotkey));
+    */
+
+    if !ev.is_type(&EventType::EV_KEY) {
+        return;
     }
-    else {
-        brightness = brightness + *BRIGHTNESS_STEP;
+    if ev.event_code == settings.profile.hotkey && ev.value != 2 {
+        event::emit(if ev.value == 1 { "hotkey_pressed" } else { "hotkey_released" }, Vec::new());
     }
-    set_brightness(brightness);
-}
 
-fn dec_brightness() {
-    let mut brightness = get_brightness();
-    if brightness <= *BRIGHTNESS_STEP {
-        brightness = 1;
-    }
-    else {
-        brightness = brightness - *BRIGHTNESS_STEP;
+    for action in combos.key_event(token, &ev.event_code, ev.value, &settings.profile.hotkey, &settings.profile.bindings) {
+        run_action(action);
     }
-    set_brightness(brightness);
-}
 
-fn inc_volume() {
-    let mut volume = get_volume();
-    if volume < *VOLUME_STEP {
-        volume = *VOLUME_STEP;
+    if ev.event_code == powerkey.code {
+        for action in powerkey.key_event(ev.value) {
+            run_powerkey_action(action);
+        }
     }
-    else {
-        volume = volume + *VOLUME_STEP;
+    for gesture in gestures.iter_mut().filter(|gesture| gesture.code == ev.event_code) {
+        for action in gesture.key_event(ev.value) {
+            run_action(action);
+        }
     }
-    set_volume(volume);
 }
 
-fn dec_volume() {
-    let mut volume = get_volume();
-    if volume < *VOLUME_STEP {
-        volume = 0;
+/// Runs a command of a control client. The switches of ogage.conf only
+/// concern buttons, a client may do everything.
+fn execute(command: Command, owner: Token, idle: &mut IdleTimer, reload_due: &mut Option<Instant>) -> Result<Vec<(&'static str, Json)>, Error> {
+    // Changing something is activity like a button push, which also undims
+    // first so the change isn't undone by the next push
+    if !matches!(
+        command,
+        Command::Status | Command::Inhibit { .. } | Command::Uninhibit(_) | Command::Inhibitors | Command::Reload
+    ) {
+        idle.button_pushed();
     }
-    else {
-        volume = volume - *VOLUME_STEP;
+
+    match command {
+        Command::Action(action) => perform(action).map(|_| Vec::new()),
+        Command::Volume(level) => {
+            reported("volume", volume_value, || set_volume(level.apply(get_volume)?))?;
+            Ok(vec![("volume", get_volume()?.into())])
+        }
+        Command::Mute(true) => reported("mute", volume_value, mute_volume).map(|_| Vec::new()),
+        Command::Mute(false) => reported("unmute", volume_value, unmute_volume).map(|_| Vec::new()),
+        Command::Brightness(level) => {
+            reported("brightness", brightness_value, || {
+                let brightness = level.apply(get_brightness)?;
+                create_es_brightness_lock();
+                let result = set_brightness(brightness);
+                remove_es_brightness_lock();
+                result
+            })?;
+            Ok(vec![("brightness", get_brightness()?.into())])
+        }
+        Command::Perf(name) => reported("perf", perf_value, || set_perf_profile(&name)).map(|_| Vec::new()),
+        Command::Status => Ok(status(idle)),
+        Command::Inhibit { kind, name, reason } => {
+            idle.inhibit(owner, kind, name, reason);
+            Ok(Vec::new())
+        }
+        Command::Uninhibit(name) => Ok(vec![("released", (idle.release(owner, name.as_deref()) as u64).into())]),
+        Command::Inhibitors => Ok(vec![("inhibitors", idle.inhibitors_json())]),
+        Command::Reload => {
+            *reload_due = Some(Instant::now());
+            Ok(Vec::new())
+        }
+        Command::Subscribe => Ok(Vec::new()),
     }
-    set_volume(volume);
 }
 
-fn mute_volume() {
-    set_volume(0);
+/// What `status` tells control clients, with null for what can't be read.
+fn status(idle: &IdleTimer) -> Vec<(&'static str, Json)> {
+    let settings = settings();
+    let radio = |kind| settings.radios.enabled(kind).ok().flatten();
+    vec![
+        ("device", Json::string(&settings.profile.name)),
+        ("volume", get_volume().ok().into()),
+        ("brightness", get_brightness().ok().into()),
+        ("dimmed", idle.dimmed().into()),
+        ("screen_off", (SCREEN_OFF_BRIGHTNESS.load(Ordering::SeqCst) != 0).into()),
+        ("performance_profile", settings.performance.active().into()),
+        ("wifi", radio(RadioKind::Wifi).into()),
+        ("bluetooth", radio(RadioKind::Bluetooth).into()),
+        ("battery", battery_status().ok().map(|status| status.name().to_string()).into()),
+        ("idle_seconds", idle.idle_time().as_secs().into()),
+        ("inhibitors", idle.inhibitors_json()),
+        ("process_inhibitors", idle.process_inhibitors_json()),
+    ]
 }
 
-fn norm_volume() {
-    set_volume(75);
+/// Opens the control socket, unless it is disabled.
+fn bind_control(registry: &Registry, config: &ControlConfig) -> Option<ControlServer> {
+    let path = config.socket.as_ref()?;
+    match ControlServer::bind(registry, CONTROL_TOKEN, FIRST_CLIENT_TOKEN, path, config.group.as_deref()) {
+        Ok(server) => {
+            println!("Control socket: {}", server.path().display());
+            Some(server)
+        }
+        Err(e) => {
+            println!("Can't open the control socket {}: {}", path.display(), e);
+            None
+        }
+    }
 }
 
-fn perf_max() {
-    Command::new("perfmax")
-        .arg("none")
-        .output()
-        .expect("Failed to execute performance");
-    blinkon();
-}
+/// Accepts control clients and answers their commands. Inhibitors of a
+/// client last as long as its connection.
+fn control_event(server: &mut ControlServer, registry: &Registry, event: &Event, idle: &mut IdleTimer, reload_due: &mut Option<Instant>) {
+    let token = event.token();
+    if server.is_listener(token) {
+        server.accept(registry);
+        return;
+    }
 
-fn perf_norm() {
-    Command::new("perfnorm")
-        .arg("none")
-        .output()
-        .expect("Failed to execute performance");
-    blinkoff();
+    if event.is_readable() || event.is_read_closed() {
+        match server.read_lines(token) {
+            Some(lines) => {
+                for line in lines {
+                    let command = Command::parse(&line);
+                    let subscribe = matches!(command, Ok(Command::Subscribe));
+                    let result = match command {
+                        Ok(Command::Subscribe) if idle.inhibitors().iter().any(|inhibitor| inhibitor.owner == token) => {
+                            Err("a connection holding an inhibitor can't subscribe, use another one".to_string())
+                        }
+                        command => command.and_then(|command| execute(command, token, idle, reload_due).map_err(|e| e.to_string())),
+                    };
+                    let ok = result.is_ok();
+                    server.send(registry, token, &control::reply(result));
+
+                    // The rest of the connection belongs to the events
+                    if subscribe && ok {
+                        if let Some((stream, pending)) = server.detach(registry, token) {
+                            event::subscribe(registry, FIRST_SUBSCRIBER_TOKEN, stream, pending);
+                        }
+                        return;
+                    }
+                }
+            }
+            None => server.remove(registry, token),
+        }
+    }
+    if !server.flush(registry, token) {
+        idle.release(token, None);
+    }
 }
 
-fn dark_on() {
-    set_brightness(10);
+/// Offers the D-Bus service, unless it is disabled.
+#[cfg(feature = "dbus-service")]
+fn connect_dbus(registry: &Registry, config: &ControlConfig, idle: &IdleTimer) -> Option<dbus_service::DbusService> {
+    let bus = config.dbus?;
+    match dbus_service::DbusService::connect(registry, DBUS_TOKEN, FIRST_INHIBIT_PIPE_TOKEN, bus, idle) {
+        Ok(service) => {
+            println!("D-Bus service: {}", dbus_service::SERVICE_NAME);
+            Some(service)
+        }
+        Err(e) => {
+            println!("Can't offer the D-Bus service: {}", e.message().unwrap_or("D-Bus error"));
+            None
+        }
+    }
 }
 
-fn dark_off() {
-    set_brightness(50);
+/// Notes volume changes made by other programs, e.g. the frontend.
+fn mixer_changed() {
+    let volume = match get_volume() {
+        Ok(volume) => volume,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if LAST_VOLUME.swap(volume, Ordering::SeqCst) != volume {
+        println!("Volume changed to {}%", volume);
+        event::emit("volume_changed", vec![("volume", volume.into())]);
+    }
 }
 
-fn wifi_on() {
-    blinkon();
-    Command::new("nmcli")
-        .args(&["radio", "wifi", "on"])
-        .output()
-        .expect("Failed to execute wifi on");
+/// Whether the event belongs to a binding of the hotkey which is held.
+fn is_hotkey_combo(settings: &Settings, combos: &ComboMatcher, ev: &InputEvent) -> bool {
+    ev.is_type(&EventType::EV_KEY)
+        && combos.is_down(&settings.profile.hotkey)
+        && settings
+            .profile
+            .bindings
+            .iter()
+            .any(|binding| binding.with_hotkey && binding.keys.contains(&ev.event_code))
 }
 
-fn wifi_off() {
-    Command::new("nmcli")
-        .args(&["radio", "wifi", "off"])
-        .output()
-        .expect("Failed to execute wifi off");
-    blinkoff();
+/// The earliest of two optional poll timeouts.
+fn min_timeout(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
 }
 
-fn suspend() {
-    Command::new("sudo")
-        .args(&["systemctl", "suspend"])
-        .output()
-        .expect("Failed to execute suspend");
+fn main() {
+    if let Err(e) = run() {
+        println!("ogage: {}", e);
+        std::process::exit(1);
+    }
 }
 
-fn power_off() {
-    Command::new("sudo")
-        .args(&["shutdown", "-h", "now"])
-        .output()
-        .expect("Failed to execute power off");
-}
+/// Prints the active settings, and warnings about them.
+fn log_settings(settings: &Settings, devices: &InputDevices, powerkey: &GestureKey, gestures: &[GestureKey]) {
+    let config = &settings.config;
+    let profile = &settings.profile;
 
-fn battery_status() -> BatteryStatus {
-    let status_str =
-        fs::read_to_string(BATTERY_STATUS_FILE).expect("Failed to read battery status");
+    for warning in config.warnings.iter() {
+        println!("Config: {}", warning);
+    }
 
-    match status_str.as_str().trim() {
-        "Unknown" => BatteryStatus::Unknown,
-        "Charging" => BatteryStatus::Charging,
-        "Discharging" => BatteryStatus::Discharging,
-        "Not charging" => BatteryStatus::NotCharging,
-        "Full" => BatteryStatus::Full,
-        _ => panic!("Unhandled battery status value"),
+    println!("\nDevice: {}\nDevice profile: {} ({})\nAuto suspend: {}\nAuto suspend timeout: {:?}\nAuto suspend stay awake while charging: {}\nAuto dim: {}\nAuto dim timeout: {:?}\nAuto dim brightness: {}%\nAuto dim stay awake while charging: {}",
+             config.device.as_deref().unwrap_or("auto"), profile.name, profile.detected_by, config.auto_suspend.enabled, config.auto_suspend.timeout, config.auto_suspend.stay_awake_while_charging, config.auto_dim.enabled, config.auto_dim.timeout, config.auto_dim.brightness, config.auto_dim.stay_awake_while_charging);
+
+    let rules = |rules: &[processes::ProcessRule]| match rules {
+        [] => "none".to_string(),
+        rules => rules.iter().map(|rule| rule.to_string()).collect::<Vec<_>>().join(", "),
+    };
+    println!(
+        "Auto suspend inhibiting processes: {}\nAuto suspend counts sound playback as activity: {}\nAuto dim inhibiting processes: {}\nAuto dim counts sound playback as activity: {}",
+        rules(&config.auto_suspend.inhibit_processes),
+        config.auto_suspend.audio_activity,
+        rules(&config.auto_dim.inhibit_processes),
+        config.auto_dim.audio_activity
+    );
+
+    println!("Allow brightness: {}\nBrightness step: {}%\nAllow volume: {}\nVolume step: {}%\nAllow wifi: {}\nAllow bluetooth: {}\nAllow performance: {}\nAllow suspend: {}", 
+        config.brightness.allowed, config.brightness.step, config.volume.allowed, config.volume.step, config.radios.allow_wifi, config.radios.allow_bluetooth, config.performance.allowed, config.power.allow_suspend);
+   
+    println!("Emulationstation Brighthness Lock File: {}", config.brightness.es_lock_file);
+    println!("Backlight: {}", settings.backlight.describe());
+    println!("Mixer: {}", settings.mixer.describe());
+    println!("Radios: {}", settings.radios.describe());
+    println!("Power: {}", settings.power.describe());
+    println!(
+        "Performance profiles: {} (active: {})",
+        settings.performance.names().join(", "),
+        settings.performance.active().unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("Hide hotkey combos from other programs: {}", config.input.hotkey_grab);
+
+    match config.input.key_repeat.as_ref() {
+        Some(settings) => println!("Key repeat: delay {:?}, interval {:?} down to {:?}", settings.delay, settings.interval, settings.min_interval),
+        None => println!("Key repeat: disabled"),
     }
-}
 
-fn create_es_brightness_lock() {
-    if !Path::new(*ES_BRIGTHNESS_LOCK_FILE).exists() {
-        std::fs::File::create(*ES_BRIGTHNESS_LOCK_FILE).expect("create failed");
+    println!("Gestures:");
+    for key in std::iter::once(powerkey).chain(gestures.iter()) {
+        for (gesture, action) in key.actions.iter() {
+            println!("\t{} {}={}", key.code, gesture, action);
+        }
+        println!(
+            "\t{} long press {:?}, double press within {:?}..{:?}",
+            key.code, key.timing.long_press, key.timing.double_press_min, key.timing.double_press_max
+        );
     }
-}
 
-fn remove_es_brightness_lock() {
-    if Path::new(*ES_BRIGTHNESS_LOCK_FILE).exists() {
-        thread::sleep(Duration::from_millis(200));
-        fs::remove_file(*ES_BRIGTHNESS_LOCK_FILE).expect("remove failed");
-    }    
+    println!("Bindings:");
+    for (i, binding) in profile.bindings.iter().enumerate() {
+        let shadowed_by = profile.bindings[..i].iter().find(|b| b.same_input(binding));
+        let missing = binding.keys.iter().find(|key| !devices.has_code(key));
+        if let Some(other) = shadowed_by {
+            println!("\t{} (warning: never fires, same buttons as {})", binding, other.action);
+        } else if let Some(key) = missing {
+            println!("\t{} (warning: no input device has {})", binding, key);
+        } else {
+            println!("\t{}", binding);
+        }
+    }
 }
 
-fn process_oga1_event(ev: &InputEvent) {
-    if ev.event_code == *BRIGHT_UP && *ALLOW_BRIGHTNESS {
-        inc_brightness();
-    } else if ev.event_code == *BRIGHT_DOWN && *ALLOW_BRIGHTNESS {
-        dec_brightness();
-    } else if ev.event_code == *VOL_UP && *ALLOW_VOLUME {
-        inc_volume();
-    } else if ev.event_code == *VOL_DOWN && *ALLOW_VOLUME {
-        dec_volume();
-    }
+fn powerkey_gestures(settings: &Settings) -> GestureKey {
+    GestureKey::new(
+        settings.profile.powerkey.clone(),
+        settings.config.powerkey.gestures.clone(),
+        settings.config.powerkey.timing.clone(),
+    )
 }
 
-fn process_event(_dev: &Device, ev: &InputEvent, hotkey: bool) {
-    /*
-    println!("Event: time {}.{} type {} code {} value {} hotkey {}",
-             ev.time.tv_sec,
-             ev.time.tv_usec,
-             ev.event_type,
-             ev.event_code,
-             ev.value,
-             hotkey);
-    */
+fn grab_hotkey(settings: &Settings) -> Option<EventCode> {
+    Some(settings.profile.hotkey.clone()).filter(|_| settings.config.input.hotkey_grab)
+}
 
-    if ev.value == 1 {
-        /*
-        println!("Event: time {}.{} type {} code {} value {} hotkey {}",
-                ev.time.tv_sec, ev.time.tv_usec, ev.event_type, ev.event_code,
-                ev.value, hotkey);
-        println!("Device: {}", *DEVICE);
-        println!("Is OGA v1.1: {}", *IS_OGA1);
-        println!("IS double push power off button active?: {}", *IS_DOUBLE_PUSH_POWERKEY_ACTIVE);
-        */
-
-        if hotkey {
-            if !*IS_OGA1 {
-                process_oga1_event(ev);
-            }
-            if ev.event_code == *MUTE && *ALLOW_VOLUME {
-                mute_volume();
-            } else if ev.event_code == *VOL_NORM && *ALLOW_VOLUME {
-                norm_volume();
-            } else if ev.event_code == PERF_MAX && *ALLOW_PERFORMANCE {
-                perf_max();
-            } else if ev.event_code == PERF_NORM && *ALLOW_PERFORMANCE {
-                perf_norm();
-            } else if ev.event_code == DARK_ON && *ALLOW_BRIGHTNESS {
-                dark_on();
-            } else if ev.event_code == DARK_OFF && *ALLOW_BRIGHTNESS {
-                dark_off();
-            } else if ev.event_code == WIFI_ON && *ALLOW_WIFI {
-                wifi_on();
-            } else if ev.event_code == WIFI_OFF && *ALLOW_WIFI {
-                wifi_off();
-            } else if ev.event_code == *SUSPEND && *ALLOW_SUSPEND {
-                suspend();
-            }
-        } else if *IS_OGA1 {
-            process_oga1_event(ev);
+/// Follows volume changes of the mixer, if it tells about them.
+fn watch_mixer(poll: &Poll, mixer: &dyn Mixer) {
+    if let Some(fd) = mixer.notify_fd() {
+        if let Err(e) = poll.registry().register(&mut SourceFd(&fd), MIXER_TOKEN, Interest::READABLE) {
+            println!("Can't follow volume changes: {}", e);
         }
     }
 }
 
-fn main() -> io::Result<()> {
+fn run() -> Result<(), Error> {
+    // Before anything could start a thread
+    let signals = SignalFd::new(&[libc::SIGHUP, libc::SIGINT, libc::SIGTERM])?;
     let mut poll = Poll::new()?;
-    let mut events = Events::with_capacity(1);
-    let mut devs: Vec<Device> = Vec::new();
-    let mut hotkey = false;
-    let mut first_push_powerkey: Option<SystemTime> = None;
-    let mut last_button_push: SystemTime = SystemTime::now();
-    let mut last_charge: SystemTime = SystemTime::now();
-    let mut auto_dim_active: bool = false;
-    let mut last_brightness: u32 = 0;
-
-    println!("\nDevice: {}\nIs OGA v1.1?: {}\nIs double push power off button active?: {}\nPOWERKEY interval time: {:?}\nPOWERKEY action: {}\nAuto suspend: {}\nAuto suspend timeout: {:?}\nAuto suspend stay awake while charging: {}\nAuto dim: {}\nAuto dim timeout: {:?}\nAuto dim brightness: {}%\nAuto dim stay awake while charging: {}",
-             *DEVICE, *IS_OGA1, *IS_DOUBLE_PUSH_POWERKEY_ACTIVE, *MAX_POWERKEY_INTERVAL_TIME,
-             match *POWERKEY_ACTION {
-                PowerkeyActions::Suspend => "suspend",
-                PowerkeyActions::Disabled => "disabled",
-                _ => "shutdown",
-            }, *AUTO_SUSPEND_ENABLED, *AUTO_SUSPEND_TIMEOUT, *AUTO_SUSPEND_STAY_AWAKE_WHILE_CHARGING, *AUTO_DIM_ENABLED, *AUTO_DIM_TIMEOUT, *AUTO_DIM_BRIGHTNESS, *AUTO_DIM_STAY_AWAKE_WHILE_CHARGING);
-
-    println!("Allow brightness: {}\nBrightness step: {}%\nAllow volume: {}\nVolume step: {}%\nAllow wifi: {}\nAllow performance: {}\nAllow suspend: {}", 
-        *ALLOW_BRIGHTNESS, *BRIGHTNESS_STEP, *ALLOW_VOLUME, *VOLUME_STEP, *ALLOW_WIFI, *ALLOW_PERFORMANCE, *ALLOW_SUSPEND);
-   
-    println!("Emulationstation Brighthness Lock File: {}", *ES_BRIGTHNESS_LOCK_FILE);
-
-    let mut i = 0;
-    for s in [
-        "/dev/input/event3",
-        "/dev/input/event2",
-        "/dev/input/event0",
-        "/dev/input/event1",
-    ]
-    .iter()
-    {
-        if !Path::new(s).exists() {
-            println!("Path {} doesn't exist", s);
-            continue;
-        }
-        let fd = File::open(Path::new(s)).unwrap();
-        let mut dev = Device::new().unwrap();
-        poll.registry()
-            .register(&mut SourceFd(&fd.as_raw_fd()), Token(i), Interest::READABLE)?;
-        dev.set_fd(fd)?;
-        devs.push(dev);
-        println!("Added device {}", s);
-        i += 1;
+    let mut events = Events::with_capacity(16);
+    let mut settings = settings();
+    let mut combos = ComboMatcher::new(settings.config.input.key_repeat.clone());
+    let mut idle = IdleTimer::new();
+    let mut state_watch = StateWatch::new();
+    let mut powerkey = powerkey_gestures(&settings);
+    let mut gestures = settings.config.input.gestures.clone();
+    // Set when the configuration changed, the files often change together
+    let mut reload_due: Option<Instant> = None;
+
+    let mut devices = InputDevices::new(poll.registry(), INPUT_DIR_TOKEN, FIRST_DEVICE_TOKEN, device_filter(&settings, &gestures))?;
+    if let Some(hotkey) = grab_hotkey(&settings) {
+        devices.grab_on_hotkey(hotkey);
+    }
+    // Pads plugged in later still show up through hotplug
+    if let Err(e) = devices.scan(poll.registry()) {
+        println!("Can't scan the input devices: {}", e);
     }
 
-    loop {
-        poll.poll(&mut events, None)?;
-
-        for event in events.iter() {
-            let dev = &mut devs[event.token().0];
-            while dev.has_event_pending() {
-                let e = dev.next_event(evdev_rs::ReadFlag::NORMAL);
-                match e {
-                    Ok(k) => {
-                        let ev = &k.1;
-                        //println!("Hotkey: {} - {} - {}", *HOTKEY, ev.event_code, hotkey);
-                        if ev.event_code == *HOTKEY {
-                            hotkey = ev.value == 1;
-                            //println!("Hotkey: {} - {}", *HOTKEY, hotkey);
-                            //let grab = if hotkey { GrabMode::Grab } else { GrabMode::Ungrab };
-                            //dev.grab(grab)?;
-                        }
-
-                        process_event(&dev, &ev, hotkey);
-
-                        if ev.event_code == POWER_OFF 
-                            && ev.value == 1
-                        {
-                            if *IS_DOUBLE_PUSH_POWERKEY_ACTIVE    
-                            {
-                                let next_first_push_powerkey: SystemTime = SystemTime::now();
-                                if first_push_powerkey.is_some() {
-                                    let diff = first_push_powerkey.unwrap().elapsed().unwrap();
-                                    first_push_powerkey = Some(next_first_push_powerkey);
-                                    //println!("diff: {:?})", diff);
-                                    if diff >= MIN_POWERKEY_ELAPSED
-                                        && diff <= *MAX_POWERKEY_INTERVAL_TIME
-                                    {
-                                        // two push at least in more than one second
-                                        match *POWERKEY_ACTION {
-                                            PowerkeyActions::Suspend => suspend(),
-                                            PowerkeyActions::Shutdown => power_off(),
-                                            _ => ()
-                                        }
-                                    }
-                                } else {
-                                    first_push_powerkey = Some(next_first_push_powerkey);
-                                }
-                            }
-                            else {
-                                match *POWERKEY_ACTION {
-                                    PowerkeyActions::Suspend => suspend(),
-                                    _ => ()
-                                }
-                            }
+    watch_mixer(&poll, settings.mixer.as_ref());
+    poll.registry().register(&mut SourceFd(&signals.as_raw_fd()), SIGNAL_TOKEN, Interest::READABLE)?;
+    let mut config_watch = None;
+    match ConfigWatch::new() {
+        Ok(watch) => {
+            poll.registry().register(&mut SourceFd(&watch.as_raw_fd()), CONFIG_TOKEN, Interest::READABLE)?;
+            config_watch = Some(watch);
+        }
+        Err(e) => println!("Can't watch the configuration files, reload with SIGHUP: {}", e),
+    }
 
-                        }
+    let mut control = bind_control(poll.registry(), &settings.config.control);
+    #[cfg(feature = "dbus-service")]
+    let mut dbus = connect_dbus(poll.registry(), &settings.config.control, &idle);
 
-                        // Variables used for both auto-suspend and auto-dimming
-                        let button_pushed = ev.value == 1;
+    log_settings(&settings, &devices, &powerkey, &gestures);
 
-                        let charging: bool = match battery_status() {
-                            BatteryStatus::Unknown => false,
-                            BatteryStatus::Charging => true,
-                            BatteryStatus::Discharging => false,
-                            BatteryStatus::NotCharging => false,
-                            BatteryStatus::Full => true,
-                        };
+    loop {
+        let timeout = std::iter::once(&powerkey)
+            .chain(gestures.iter())
+            .map(|key| key.next_timeout())
+            .chain(std::iter::once(reload_due.map(|due| due.saturating_duration_since(Instant::now()))))
+            .chain(std::iter::once(state_watch.next_timeout()))
+            .fold(min_timeout(idle.next_timeout(), combos.next_timeout(&settings.profile.bindings)), min_timeout);
+        poll.poll(&mut events, timeout)?;
 
-                        if button_pushed {
-                            last_button_push = SystemTime::now();
-                        }
+        for event in events.iter() {
+            if event.token() == INPUT_DIR_TOKEN {
+                devices.hotplug(poll.registry());
+                continue;
+            }
+            if event.token() == MIXER_TOKEN {
+                if settings.mixer.changed() {
+                    mixer_changed();
+                }
+                continue;
+            }
+            if event.token() == CONFIG_TOKEN {
+                if config_watch.as_mut().is_some_and(|watch| watch.changed()) {
+                    reload_due = Some(Instant::now() + RELOAD_DELAY);
+                }
+                continue;
+            }
+            if event.token() == SIGNAL_TOKEN {
+                match signals.read_signals() {
+                    // Returning cleans up, e.g. removes the control socket
+                    Ok(received) if received.contains(&libc::SIGINT) || received.contains(&libc::SIGTERM) => return Ok(()),
+                    Ok(received) if received.contains(&libc::SIGHUP) => reload_due = Some(Instant::now()),
+                    Ok(_) => (),
+                    Err(e) => println!("Can't read signals: {}", e),
+                }
+                continue;
+            }
+            #[cfg(feature = "dbus-service")]
+            if event.token() == DBUS_TOKEN {
+                if let Some(service) = dbus.as_mut() {
+                    if !service.dispatch(poll.registry(), &mut idle, &mut reload_due) {
+                        println!("Lost the D-Bus connection");
+                        dbus.take().unwrap().close(poll.registry(), &mut idle);
+                    }
+                }
+                continue;
+            }
+            #[cfg(feature = "dbus-service")]
+            if let Some(service) = dbus.as_mut().filter(|service| service.owns(event.token())) {
+                service.pipe_event(poll.registry(), event.token(), &mut idle);
+                continue;
+            }
+            if event::owns(event.token()) {
+                event::subscriber_event(poll.registry(), event);
+                continue;
+            }
+            if let Some(server) = control.as_mut().filter(|server| server.owns(event.token())) {
+                control_event(server, poll.registry(), event, &mut idle, &mut reload_due);
+                continue;
+            }
 
-                        if charging {
-                            last_charge = SystemTime::now();
+            let input = match devices.get_mut(event.token()) {
+                Some(input) => input,
+                None => continue,
+            };
+            let mut gone = event.is_read_closed() || event.is_error();
+            while input.dev.has_event_pending() {
+                match input.dev.next_event(evdev_rs::ReadFlag::NORMAL) {
+                    Ok((_, ev)) => {
+                        process_event(&settings, &mut combos, &mut powerkey, &mut gestures, event.token(), &ev);
+                        input.pass_through(&ev, &settings.profile.hotkey, is_hotkey_combo(&settings, &combos, &ev));
+
+                        if ev.value == 1 {
+                            idle.button_pushed();
                         }
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                    // An unplugged pad keeps looking readable, but only
+                    // gives ENODEV from then on
+                    Err(_) => {
+                        gone = true;
+                        break;
+                    }
+                }
+            }
 
-                        if *AUTO_SUSPEND_ENABLED {
-                            /*
-                            println!("Event: time {}.{} type {} code {} value {} hotkey {}\nLast Push Button Time {:?}\nActual Time {:?}\n",
-                                     ev.time.tv_sec, ev.time.tv_usec, ev.event_type, ev.event_code,
-                                     ev.value, hotkey, last_button_push, SystemTime::now());
-                            */
-                            let button_push_timed_out =
-                                last_button_push.elapsed().unwrap() > *AUTO_SUSPEND_TIMEOUT;
-                            let charge_timed_out =
-                                last_charge.elapsed().unwrap() > *AUTO_SUSPEND_TIMEOUT;
-
-                            if (*AUTO_SUSPEND_STAY_AWAKE_WHILE_CHARGING
-                                && button_push_timed_out
-                                && charge_timed_out)
-                                || (!*AUTO_SUSPEND_STAY_AWAKE_WHILE_CHARGING
-                                    && button_push_timed_out)
-                            {
-                                suspend();
-                                last_button_push = SystemTime::now();
-                                last_charge = SystemTime::now();
-                            }
-                        }
+            if gone {
+                devices.remove(poll.registry(), event.token());
+                // Don't leave keys stuck if their pad went away while held
+                combos.device_removed(event.token(), &settings.profile.hotkey, &settings.profile.bindings);
+            }
+        }
 
-                        if *AUTO_DIM_ENABLED {
-                            if auto_dim_active {
-                                if button_pushed
-                                    || (*AUTO_DIM_STAY_AWAKE_WHILE_CHARGING && charging)
-                                {
-                                    // Restore previous brightness
-                                    auto_dim_active = false;
-                                    create_es_brightness_lock();
-                                    set_brightness(last_brightness);
-                                    remove_es_brightness_lock();
-                                }
-                            } else {
-                                let button_push_timed_out =
-                                    last_button_push.elapsed().unwrap() > *AUTO_DIM_TIMEOUT;
-                                let charge_timed_out =
-                                    last_charge.elapsed().unwrap() > *AUTO_DIM_TIMEOUT;
-
-                                if (*AUTO_DIM_STAY_AWAKE_WHILE_CHARGING
-                                    && button_push_timed_out
-                                    && charge_timed_out)
-                                    || (!*AUTO_DIM_STAY_AWAKE_WHILE_CHARGING
-                                        && button_push_timed_out)
-                                {
-                                    // Save current brightness and dim the screen
-                                    auto_dim_active = true;
-                                    last_brightness = get_brightness();
-                                    create_es_brightness_lock();
-                                    set_brightness(*AUTO_DIM_BRIGHTNESS);
-                                    remove_es_brightness_lock();
-                                }
-                            }
-                        }
+        if reload_due.is_some_and(|due| due <= Instant::now()) {
+            reload_due = None;
+            println!("Reloading the configuration");
+            if let Some(watch) = config_watch.as_mut() {
+                watch.watch_profiles();
+            }
+            let previous = std::mem::replace(&mut settings, reload());
+
+            // The idle timer only reads the new timeouts, keeping its times
+            combos.reconfigure(settings.config.input.key_repeat.clone());
+            powerkey = powerkey_gestures(&settings);
+            gestures = settings.config.input.gestures.clone();
+            let filter = device_filter(&settings, &gestures);
+            for token in devices.reconfigure(poll.registry(), filter, grab_hotkey(&settings)) {
+                combos.device_removed(token, &settings.profile.hotkey, &settings.profile.bindings);
+            }
+            if !Arc::ptr_eq(&previous.mixer, &settings.mixer) {
+                if let Some(fd) = previous.mixer.notify_fd() {
+                    let _ = poll.registry().deregister(&mut SourceFd(&fd));
+                }
+                watch_mixer(&poll, settings.mixer.as_ref());
+            }
+            let (old, new) = (&previous.config.control, &settings.config.control);
+            if old.socket != new.socket || old.group != new.group {
+                if let Some(server) = control.take() {
+                    for token in server.close(poll.registry()) {
+                        idle.release(token, None);
                     }
-                    _ => (),
                 }
+                control = bind_control(poll.registry(), &settings.config.control);
+            }
+            #[cfg(feature = "dbus-service")]
+            if previous.config.control.dbus != settings.config.control.dbus {
+                if let Some(service) = dbus.take() {
+                    service.close(poll.registry(), &mut idle);
+                }
+                dbus = connect_dbus(poll.registry(), &settings.config.control, &idle);
+            }
+
+            log_settings(&settings, &devices, &powerkey, &gestures);
+            event::emit("config_reloaded", vec![("warnings", Json::Array(settings.config.warnings.iter().map(|w| Json::string(w)).collect()))]);
+        }
+
+        for action in combos.check(&settings.profile.bindings) {
+            run_action(action);
+        }
+        for action in powerkey.check() {
+            run_powerkey_action(action);
+        }
+        for gesture in gestures.iter_mut() {
+            for action in gesture.check() {
+                run_action(action);
             }
         }
+        idle.check();
+        state_watch.check();
+        #[cfg(feature = "dbus-service")]
+        if let Some(service) = dbus.as_mut() {
+            service.publish(&idle);
+        }
     }
 }
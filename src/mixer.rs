@@ -0,0 +1,416 @@
+use libc::{c_int, c_long, c_ulong};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+static ASOUND_DIR: &str = "/proc/asound";
+
+// From <sound/asound.h>
+const SNDRV_CTL_ELEM_IFACE_MIXER: c_int = 2;
+const SNDRV_CTL_ELEM_TYPE_BOOLEAN: c_int = 1;
+const SNDRV_CTL_ELEM_TYPE_INTEGER: c_int = 2;
+const SNDRV_CTL_EVENT_ELEM: c_int = 0;
+const SNDRV_CTL_EVENT_MASK_VALUE: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ElemId {
+    numid: u32,
+    iface: c_int,
+    device: u32,
+    subdevice: u32,
+    name: [u8; 44],
+    index: u32,
+}
+
+#[repr(C)]
+struct ElemInfo {
+    id: ElemId,
+    type_: c_int,
+    access: u32,
+    count: u32,
+    owner: c_int,
+    /// Union whose integer variant is `{ long min, max, step }`.
+    value: [u64; 16],
+    reserved: [u8; 64],
+}
+
+#[repr(C)]
+union ElemValues {
+    integer: [c_long; 128],
+    integer64: [i64; 64],
+}
+
+#[repr(C)]
+struct ElemValue {
+    id: ElemId,
+    indirect: u32,
+    value: ElemValues,
+    reserved: [u8; 128],
+}
+
+#[repr(C)]
+struct CtlEvent {
+    type_: c_int,
+    mask: u32,
+    id: ElemId,
+}
+
+// The layouts above are the kernel's ABI, which has `long` in ElemValue
+const _: () = assert!(mem::size_of::<ElemId>() == 64);
+const _: () = assert!(mem::size_of::<ElemInfo>() == 272);
+const _: () = assert!(mem::size_of::<CtlEvent>() == 72);
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(mem::size_of::<ElemValue>() == 1224);
+// 64 bit integers are only 4 byte aligned on i386
+#[cfg(all(target_pointer_width = "32", target_arch = "x86"))]
+const _: () = assert!(mem::size_of::<ElemValue>() == 708);
+#[cfg(all(target_pointer_width = "32", not(target_arch = "x86")))]
+const _: () = assert!(mem::size_of::<ElemValue>() == 712);
+
+const fn iowr(nr: c_ulong, size: usize) -> c_ulong {
+    (3 << 30) | ((size as c_ulong) << 16) | ((b'U' as c_ulong) << 8) | nr
+}
+
+const SNDRV_CTL_IOCTL_ELEM_INFO: c_ulong = iowr(0x11, mem::size_of::<ElemInfo>());
+const SNDRV_CTL_IOCTL_ELEM_READ: c_ulong = iowr(0x12, mem::size_of::<ElemValue>());
+const SNDRV_CTL_IOCTL_ELEM_WRITE: c_ulong = iowr(0x13, mem::size_of::<ElemValue>());
+const SNDRV_CTL_IOCTL_SUBSCRIBE_EVENTS: c_ulong = iowr(0x16, mem::size_of::<c_int>());
+
+/// Sound volume control, in percent of the control's range.
+pub trait Mixer: Send + Sync {
+    fn volume(&self) -> io::Result<u32>;
+    fn set_volume(&self, percent: u32) -> io::Result<()>;
+    /// Mixers without a mute switch mute by setting the volume to 0.
+    fn set_muted(&self, muted: bool) -> io::Result<()>;
+    /// What the volume is read from, for the logs.
+    fn describe(&self) -> String;
+
+    /// Descriptor which turns readable when the volume may have changed.
+    fn notify_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Reads the pending change notifications and tells whether one of
+    /// them concerns the volume.
+    fn changed(&self) -> bool {
+        false
+    }
+}
+
+/// A volume control of an ALSA card, driven through the kernel control
+/// interface. Change notifications come from the same descriptor.
+pub struct AlsaMixer {
+    fd: RawFd,
+    card: u32,
+    volume: ElemId,
+    /// Number of channels of the volume control.
+    channels: u32,
+    min: c_long,
+    max: c_long,
+    switch: Option<(ElemId, u32)>,
+}
+
+/// Runs `amixer`, as older ogage versions did.
+pub struct Amixer {
+    card: u32,
+    control: String,
+}
+
+impl AlsaMixer {
+    /// Opens the `<control> Playback Volume` or `<control> Volume` control,
+    /// and its matching switch if any, of the ALSA card `card`.
+    pub fn open(card: u32, control: &str) -> io::Result<AlsaMixer> {
+        let path = CString::new(format!("/dev/snd/controlC{}", card))?;
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Closes the descriptor on errors from here on
+        let mut mixer = AlsaMixer {
+            fd,
+            card,
+            volume: elem_id(""),
+            channels: 0,
+            min: 0,
+            max: 0,
+            switch: None,
+        };
+
+        let volume_names = [format!("{} Playback Volume", control), format!("{} Volume", control), control.to_string()];
+        let info = volume_names
+            .iter()
+            .filter_map(|name| mixer.info(name).ok())
+            .find(|info| info.type_ == SNDRV_CTL_ELEM_TYPE_INTEGER)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no volume control '{}' on card {}", control, card))
+            })?;
+        let (min, max) = unsafe {
+            let range: &[c_long; 3] = &*(info.value.as_ptr() as *const [c_long; 3]);
+            (range[0], range[1])
+        };
+        if max <= min {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("'{}' has an empty range", control)));
+        }
+        mixer.volume = info.id;
+        mixer.channels = info.count.min(128);
+        mixer.min = min;
+        mixer.max = max;
+
+        let switch_names = [format!("{} Playback Switch", control), format!("{} Switch", control)];
+        mixer.switch = switch_names
+            .iter()
+            .filter_map(|name| mixer.info(name).ok())
+            .find(|info| info.type_ == SNDRV_CTL_ELEM_TYPE_BOOLEAN)
+            .map(|info| (info.id, info.count.min(128)));
+
+        let subscribe: c_int = 1;
+        if unsafe { libc::ioctl(fd, SNDRV_CTL_IOCTL_SUBSCRIBE_EVENTS as _, &subscribe) } < 0 {
+            println!("Can't follow volume changes of card {}: {}", card, io::Error::last_os_error());
+        }
+
+        Ok(mixer)
+    }
+
+    fn info(&self, name: &str) -> io::Result<ElemInfo> {
+        let mut info: ElemInfo = unsafe { mem::zeroed() };
+        info.id = elem_id(name);
+        if unsafe { libc::ioctl(self.fd, SNDRV_CTL_IOCTL_ELEM_INFO as _, &mut info) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info)
+    }
+
+    fn read(&self, id: &ElemId) -> io::Result<ElemValue> {
+        let mut value: ElemValue = unsafe { mem::zeroed() };
+        value.id = *id;
+        if unsafe { libc::ioctl(self.fd, SNDRV_CTL_IOCTL_ELEM_READ as _, &mut value) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value)
+    }
+
+    /// Sets every channel of the control to `raw`.
+    fn write(&self, id: &ElemId, channels: u32, raw: c_long) -> io::Result<()> {
+        let mut value: ElemValue = unsafe { mem::zeroed() };
+        value.id = *id;
+        unsafe {
+            for channel in value.value.integer.iter_mut().take(channels as usize) {
+                *channel = raw;
+            }
+        }
+        if unsafe { libc::ioctl(self.fd, SNDRV_CTL_IOCTL_ELEM_WRITE as _, &mut value) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Mixer for AlsaMixer {
+    fn volume(&self) -> io::Result<u32> {
+        let value = self.read(&self.volume)?;
+        // The loudest channel, so a balance setting doesn't lower the volume
+        let raw = unsafe { value.value.integer[..self.channels as usize].iter().copied().max() }.unwrap_or(self.min);
+        let range = wide(self.max - self.min);
+        Ok(((wide(raw.clamp(self.min, self.max) - self.min) * 100 + range / 2) / range) as u32)
+    }
+
+    fn set_volume(&self, percent: u32) -> io::Result<()> {
+        let range = wide(self.max - self.min);
+        let raw = self.min + ((percent.min(100) as i64 * range + 50) / 100) as c_long;
+        self.write(&self.volume, self.channels, raw)
+    }
+
+    fn set_muted(&self, muted: bool) -> io::Result<()> {
+        match self.switch {
+            // The switch tells whether sound plays
+            Some((id, channels)) => self.write(&id, channels, if muted { 0 } else { 1 }),
+            None if muted => self.set_volume(0),
+            None => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let name = |id: &ElemId| {
+            let end = id.name.iter().position(|b| *b == 0).unwrap_or(id.name.len());
+            String::from_utf8_lossy(&id.name[..end]).into_owned()
+        };
+        match self.switch {
+            Some((switch, _)) => format!("card {} '{}' and '{}'", self.card, name(&self.volume), name(&switch)),
+            None => format!("card {} '{}'", self.card, name(&self.volume)),
+        }
+    }
+
+    fn notify_fd(&self) -> Option<RawFd> {
+        Some(self.fd)
+    }
+
+    fn changed(&self) -> bool {
+        let mut changed = false;
+        let ours = |id: &ElemId| id.numid == self.volume.numid || self.switch.is_some_and(|(s, _)| id.numid == s.numid);
+
+        loop {
+            let mut event: CtlEvent = unsafe { mem::zeroed() };
+            let size = mem::size_of::<CtlEvent>();
+            let read = unsafe { libc::read(self.fd, &mut event as *mut CtlEvent as *mut libc::c_void, size) };
+            if read < size as isize {
+                return changed;
+            }
+            if event.type_ == SNDRV_CTL_EVENT_ELEM && event.mask & SNDRV_CTL_EVENT_MASK_VALUE != 0 && ours(&event.id) {
+                changed = true;
+            }
+        }
+    }
+}
+
+impl Drop for AlsaMixer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl Mixer for Amixer {
+    fn volume(&self) -> io::Result<u32> {
+        let output = Command::new("amixer")
+            .args(["-c", &self.card.to_string(), "sget", &self.control])
+            .stdout(Stdio::piped())
+            .output()?;
+        let lines_str = String::from_utf8_lossy(&output.stdout);
+
+        lines_str
+            .lines()
+            .last()
+            .and_then(|last_line| {
+                last_line
+                    .split_whitespace()
+                    .filter(|x| x.starts_with('[') && !x.contains("dB"))
+                    .map(|s| s.trim_matches(&['[', ']', '%'] as &[_]))
+                    .find_map(|s| s.trim().parse::<u32>().ok())
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unexpected amixer output"))
+    }
+
+    fn set_volume(&self, percent: u32) -> io::Result<()> {
+        let volume_str = percent.to_string() + "%";
+        let output = Command::new("amixer")
+            .args(["-c", &self.card.to_string(), "sset", &self.control, &volume_str])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "amixer {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_muted(&self, muted: bool) -> io::Result<()> {
+        if muted {
+            self.set_volume(0)?;
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("amixer card {} '{}'", self.card, self.control)
+    }
+}
+
+/// Picks the mixer from the `mixer` setting: `amixer`, or nothing for the
+/// ALSA control interface. amixer stands in when the control can't be used.
+pub fn select(setting: Option<&str>, card: u32, control: &str) -> Box<dyn Mixer> {
+    if setting.map(|setting| setting.trim()) == Some("amixer") {
+        return Box::new(Amixer { card, control: control.to_string() });
+    }
+
+    match AlsaMixer::open(card, control) {
+        Ok(mixer) => Box::new(mixer),
+        Err(e) => {
+            println!("Can't use the ALSA mixer ({}), falling back to amixer", e);
+            Box::new(Amixer { card, control: control.to_string() })
+        }
+    }
+}
+
+/// Whether any card plays sound: a playback substream of
+/// /proc/asound/card*/pcm*p/sub* is in the RUNNING state. Paused and
+/// merely opened streams don't count.
+pub fn playback_running() -> bool {
+    let entries = |dir: &Path, prefix: &'static str, suffix: &'static str| {
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(move |entry| entry.file_name().to_str().is_some_and(|name| name.starts_with(prefix) && name.ends_with(suffix)))
+            .map(|entry| entry.path())
+    };
+
+    entries(Path::new(ASOUND_DIR), "card", "")
+        .flat_map(|card| entries(&card, "pcm", "p").collect::<Vec<_>>())
+        .flat_map(|pcm| entries(&pcm, "sub", "").collect::<Vec<_>>())
+        .any(|sub| {
+            fs::read_to_string(sub.join("status"))
+                .map(|status| status.lines().any(|line| line.trim() == "state: RUNNING"))
+                .unwrap_or(false)
+        })
+}
+
+/// `long` is only 32 bits wide on armhf.
+#[allow(clippy::useless_conversion)]
+fn wide(value: c_long) -> i64 {
+    i64::from(value)
+}
+
+fn elem_id(name: &str) -> ElemId {
+    let mut id = ElemId {
+        numid: 0,
+        iface: SNDRV_CTL_ELEM_IFACE_MIXER,
+        device: 0,
+        subdevice: 0,
+        name: [0; 44],
+        index: 0,
+    };
+    for (dst, src) in id.name.iter_mut().take(43).zip(name.bytes()) {
+        *dst = src;
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ioctl_numbers_match_the_kernel() {
+        assert_eq!(SNDRV_CTL_IOCTL_ELEM_INFO, 0xc110_5511);
+        assert_eq!(SNDRV_CTL_IOCTL_SUBSCRIBE_EVENTS, 0xc004_5516);
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert_eq!(SNDRV_CTL_IOCTL_ELEM_READ, 0xc4c8_5512);
+            assert_eq!(SNDRV_CTL_IOCTL_ELEM_WRITE, 0xc4c8_5513);
+        }
+        #[cfg(all(target_pointer_width = "32", not(target_arch = "x86")))]
+        {
+            assert_eq!(SNDRV_CTL_IOCTL_ELEM_READ, 0xc2c8_5512);
+            assert_eq!(SNDRV_CTL_IOCTL_ELEM_WRITE, 0xc2c8_5513);
+        }
+    }
+
+    #[test]
+    fn elem_names_are_nul_terminated() {
+        let id = elem_id("Playback");
+        assert_eq!(&id.name[..9], b"Playback\0");
+        assert_eq!(id.iface, SNDRV_CTL_ELEM_IFACE_MIXER);
+
+        let long = "x".repeat(60);
+        assert_eq!(elem_id(&long).name[43], 0);
+    }
+}
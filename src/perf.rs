@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::profile::split_list;
+
+pub static CPUFREQ_DIR: &str = "/sys/devices/system/cpu/cpufreq";
+pub static DEVFREQ_DIR: &str = "/sys/class/devfreq";
+
+/// Prefix of the `ogage.conf` keys describing performance profiles, e.g.
+/// `perf.max.cpu_governor=performance`.
+pub static PERF_PREFIX: &str = "perf.";
+
+/// cpufreq settings of a policy; unset values are left alone.
+#[derive(Clone, Default)]
+struct FreqSettings {
+    governor: Option<String>,
+    /// In kHz, like cpufreq.
+    min_freq: Option<String>,
+    max_freq: Option<String>,
+}
+
+/// A named set of CPU and devfreq (GPU, memory controller) settings.
+///
+/// `perf.<name>.cpu_governor`, `cpu_min_freq` and `cpu_max_freq` apply to
+/// every cpufreq policy, `perf.<name>.<policy>.governor`, `min_freq` and
+/// `max_freq` to a single one, e.g. `policy0`. `perf.<name>.devfreq_governor`
+/// applies to every devfreq device, `perf.<name>.devfreq.<device>.governor`
+/// to a single one. `perf.<name>.hook` is a command run afterwards.
+#[derive(Clone)]
+pub struct PerfProfile {
+    pub name: String,
+    cpu: FreqSettings,
+    policies: Vec<(String, FreqSettings)>,
+    devfreq_governor: Option<String>,
+    devfreq_devices: Vec<(String, String)>,
+    hook: Option<String>,
+}
+
+/// The performance profiles and which one is active.
+pub struct Performance {
+    profiles: Vec<PerfProfile>,
+    active: Mutex<Option<usize>>,
+    /// Usually `CPUFREQ_DIR` and `DEVFREQ_DIR`.
+    cpufreq: PathBuf,
+    devfreq: PathBuf,
+}
+
+impl PerfProfile {
+    fn parse(name: &str, properties: &HashMap<String, String>, warnings: &mut Vec<String>) -> PerfProfile {
+        let prefix = format!("{}{}.", PERF_PREFIX, name);
+        let mut profile = PerfProfile {
+            name: name.to_string(),
+            cpu: FreqSettings::default(),
+            policies: Vec::new(),
+            devfreq_governor: None,
+            devfreq_devices: Vec::new(),
+            hook: None,
+        };
+        let mut hook = None;
+
+        let mut keys: Vec<(&str, &str)> = properties
+            .iter()
+            .filter_map(|(key, value)| key.strip_prefix(&prefix).map(|key| (key, value.trim())))
+            .collect();
+        keys.sort_unstable();
+
+        for (key, value) in keys {
+            let value = value.to_string();
+            match key {
+                "cpu_governor" => profile.cpu.governor = Some(value),
+                "cpu_min_freq" => profile.cpu.min_freq = Some(value),
+                "cpu_max_freq" => profile.cpu.max_freq = Some(value),
+                "devfreq_governor" => profile.devfreq_governor = Some(value),
+                "hook" => hook = Some(value),
+                _ => {
+                    if let Some(device) = key.strip_prefix("devfreq.").and_then(|key| key.strip_suffix(".governor")) {
+                        profile.devfreq_devices.push((device.to_string(), value));
+                        continue;
+                    }
+                    let policy = key.split_once('.').filter(|(policy, _)| policy.starts_with("policy"));
+                    let (policy, setting) = match policy {
+                        Some(policy) => policy,
+                        None => {
+                            warnings.push(format!("Ignoring {}{}: unknown setting", prefix, key));
+                            continue;
+                        }
+                    };
+                    let index = match profile.policies.iter().position(|(p, _)| p == policy) {
+                        Some(index) => index,
+                        None => {
+                            profile.policies.push((policy.to_string(), FreqSettings::default()));
+                            profile.policies.len() - 1
+                        }
+                    };
+                    let settings = &mut profile.policies[index].1;
+                    match setting {
+                        "governor" => settings.governor = Some(value),
+                        "min_freq" => settings.min_freq = Some(value),
+                        "max_freq" => settings.max_freq = Some(value),
+                        _ => warnings.push(format!("Ignoring {}{}: unknown setting", prefix, key)),
+                    }
+                }
+            }
+        }
+
+        // Profiles configured the old way keep running the scripts
+        profile.hook = match hook {
+            Some(hook) if hook.is_empty() => None,
+            Some(hook) => Some(hook),
+            None if profile.is_empty() && name == "max" => Some("perfmax none".to_string()),
+            None if profile.is_empty() && name == "norm" => Some("perfnorm none".to_string()),
+            None => None,
+        };
+        profile
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cpu.governor.is_none()
+            && self.cpu.min_freq.is_none()
+            && self.cpu.max_freq.is_none()
+            && self.policies.is_empty()
+            && self.devfreq_governor.is_none()
+            && self.devfreq_devices.is_empty()
+    }
+
+    /// Applies the profile, carrying on past the settings which fail.
+    fn apply(&self, cpufreq: &Path, devfreq: &Path) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for policy in list_dir(cpufreq).iter().filter(|path| file_name(path).starts_with("policy")) {
+            let mut settings = self.cpu.clone();
+            if let Some((_, own)) = self.policies.iter().find(|(name, _)| name == file_name(policy)) {
+                settings.governor = own.governor.clone().or(settings.governor);
+                settings.min_freq = own.min_freq.clone().or(settings.min_freq);
+                settings.max_freq = own.max_freq.clone().or(settings.max_freq);
+            }
+            apply_freq(policy, &settings, &mut errors);
+        }
+        for (name, _) in self.policies.iter() {
+            if !cpufreq.join(name).exists() {
+                errors.push(format!("no cpufreq {}", name));
+            }
+        }
+
+        for device in list_dir(devfreq) {
+            let own = self.devfreq_devices.iter().find(|(name, _)| name == file_name(&device));
+            if let Some(governor) = own.map(|(_, governor)| governor).or(self.devfreq_governor.as_ref()) {
+                write_setting(&device.join("governor"), governor, &mut errors);
+            }
+        }
+        for (name, _) in self.devfreq_devices.iter() {
+            if !devfreq.join(name).exists() {
+                errors.push(format!("no devfreq device {}", name));
+            }
+        }
+
+        if let Some(hook) = self.hook.as_ref() {
+            let mut words = hook.split_whitespace();
+            if let Some(program) = words.next() {
+                match Command::new(program).args(words).output() {
+                    Ok(output) if !output.status.success() => errors.push(format!("'{}' failed: {}", hook, output.status)),
+                    Ok(_) => (),
+                    Err(e) => errors.push(format!("can't run '{}': {}", hook, e)),
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Whether sysfs currently shows the settings of the profile. Profiles
+    /// made only of a hook never match.
+    fn is_applied(&self, cpufreq: &Path, devfreq: &Path) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let policies = list_dir(cpufreq);
+        let cpu_applied = policies.iter().filter(|path| file_name(path).starts_with("policy")).all(|policy| {
+            let own = self.policies.iter().find(|(name, _)| name == file_name(policy)).map(|(_, own)| own);
+            let setting = |own: Option<&String>, all: &Option<String>, file: &str| {
+                own.or(all.as_ref()).is_none_or(|value| read_setting(&policy.join(file)).as_deref() == Some(value))
+            };
+            setting(own.and_then(|own| own.governor.as_ref()), &self.cpu.governor, "scaling_governor")
+                && setting(own.and_then(|own| own.min_freq.as_ref()), &self.cpu.min_freq, "scaling_min_freq")
+                && setting(own.and_then(|own| own.max_freq.as_ref()), &self.cpu.max_freq, "scaling_max_freq")
+        });
+
+        let devfreq_applied = list_dir(devfreq).iter().all(|device| {
+            let own = self.devfreq_devices.iter().find(|(name, _)| name == file_name(device));
+            own.map(|(_, governor)| governor)
+                .or(self.devfreq_governor.as_ref())
+                .is_none_or(|governor| read_setting(&device.join("governor")).as_deref() == Some(governor))
+        });
+
+        cpu_applied && devfreq_applied
+    }
+}
+
+impl Performance {
+    /// Manages `profiles` on the cpufreq policies in `cpufreq` and the
+    /// devfreq devices in `devfreq`.
+    pub fn new(profiles: Vec<PerfProfile>, cpufreq: &Path, devfreq: &Path) -> Performance {
+        let active = profiles.iter().position(|profile| profile.is_applied(cpufreq, devfreq));
+
+        Performance {
+            profiles,
+            active: Mutex::new(active),
+            cpufreq: cpufreq.to_path_buf(),
+            devfreq: devfreq.to_path_buf(),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.profiles.iter().map(|profile| profile.name.as_str()).collect()
+    }
+
+    /// Name of the profile applied last, or found applied at startup.
+    pub fn active(&self) -> Option<String> {
+        let active = *self.active.lock().unwrap();
+        active.map(|index| self.profiles[index].name.clone())
+    }
+
+    /// Takes over the active profile of `previous` when sysfs doesn't tell,
+    /// as for profiles made only of a hook.
+    pub fn inherit_active(&self, previous: &Performance) {
+        let mut active = self.active.lock().unwrap();
+        if active.is_none() {
+            if let Some(name) = previous.active() {
+                *active = self.profiles.iter().position(|profile| profile.name == name);
+            }
+        }
+    }
+
+    /// Applies the profile called `name`. Settings which can't be applied
+    /// are skipped and reported in the error, the profile then doesn't
+    /// become the active one.
+    pub fn apply(&self, name: &str) -> Result<(), Error> {
+        let index = self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+            .ok_or_else(|| Error::Unknown {
+                what: "performance profile",
+                name: name.to_string(),
+            })?;
+        self.apply_index(index)
+    }
+
+    /// Applies the profile following the active one and returns its name,
+    /// `None` when there is no profile.
+    pub fn cycle(&self) -> Result<Option<&str>, Error> {
+        if self.profiles.is_empty() {
+            return Ok(None);
+        }
+        let next = match *self.active.lock().unwrap() {
+            Some(index) => (index + 1) % self.profiles.len(),
+            None => 0,
+        };
+        self.apply_index(next)?;
+        Ok(Some(&self.profiles[next].name))
+    }
+
+    fn apply_index(&self, index: usize) -> Result<(), Error> {
+        let profile = &self.profiles[index];
+        let errors = profile.apply(&self.cpufreq, &self.devfreq);
+        if !errors.is_empty() {
+            return Err(Error::PerfProfile {
+                name: profile.name.clone(),
+                errors,
+            });
+        }
+        *self.active.lock().unwrap() = Some(index);
+        println!("Performance profile '{}'", profile.name);
+        Ok(())
+    }
+}
+
+/// Reads the profiles listed in `perf_profiles`, `norm` and `max` when none
+/// is.
+pub fn parse_profiles(properties: &HashMap<String, String>, warnings: &mut Vec<String>) -> Vec<PerfProfile> {
+    let names = match properties.get("perf_profiles") {
+        Some(names) => split_list(names),
+        None => vec!["norm".to_string(), "max".to_string()],
+    };
+
+    let mut orphans: Vec<&str> = properties
+        .keys()
+        .filter_map(|key| key.strip_prefix(PERF_PREFIX))
+        .filter_map(|rest| rest.split_once('.').map(|(name, _)| name))
+        .filter(|name| !names.iter().any(|n| n == name))
+        .collect();
+    orphans.sort_unstable();
+    orphans.dedup();
+    for name in orphans {
+        warnings.push(format!("Ignoring {}{}.*: '{}' isn't listed in perf_profiles", PERF_PREFIX, name, name));
+    }
+
+    names.iter().map(|name| PerfProfile::parse(name, properties, warnings)).collect()
+}
+
+fn apply_freq(policy: &Path, settings: &FreqSettings, errors: &mut Vec<String>) {
+    if let Some(governor) = settings.governor.as_ref() {
+        write_setting(&policy.join("scaling_governor"), governor, errors);
+    }
+
+    // The kernel refuses a minimum above the maximum and the other way
+    // round, so the maximum goes first and again last if it failed
+    let max_path = policy.join("scaling_max_freq");
+    let max_failed = settings.max_freq.as_ref().is_some_and(|max| fs::write(&max_path, max).is_err());
+    if let Some(min) = settings.min_freq.as_ref() {
+        write_setting(&policy.join("scaling_min_freq"), min, errors);
+    }
+    if max_failed {
+        if let Some(max) = settings.max_freq.as_ref() {
+            write_setting(&max_path, max, errors);
+        }
+    }
+}
+
+fn write_setting(path: &Path, value: &str, errors: &mut Vec<String>) {
+    if let Err(e) = fs::write(path, value) {
+        errors.push(format!("can't write '{}' to {}: {}", value, path.display(), e));
+    }
+}
+
+fn read_setting(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_string())
+}
+
+fn list_dir(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|name| name.to_str()).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// cpufreq policies and devfreq devices in the temporary directory,
+    /// removed when dropped.
+    struct FakeSysfs(PathBuf);
+
+    impl FakeSysfs {
+        fn new(test: &str, policies: &[&str], devices: &[&str]) -> FakeSysfs {
+            let root = std::env::temp_dir().join(format!("ogage-perf-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for policy in policies {
+                let dir = root.join("cpufreq").join(policy);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("scaling_governor"), "schedutil\n").unwrap();
+                fs::write(dir.join("scaling_min_freq"), "408000\n").unwrap();
+                fs::write(dir.join("scaling_max_freq"), "1512000\n").unwrap();
+            }
+            for device in devices {
+                let dir = root.join("devfreq").join(device);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("governor"), "simple_ondemand\n").unwrap();
+            }
+            FakeSysfs(root)
+        }
+
+        fn performance(&self, profiles: Vec<PerfProfile>) -> Performance {
+            Performance::new(profiles, &self.0.join("cpufreq"), &self.0.join("devfreq"))
+        }
+
+        fn read(&self, path: &str) -> String {
+            read_setting(&self.0.join(path)).unwrap()
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn profiles(entries: &[(&str, &str)]) -> Vec<PerfProfile> {
+        let properties = entries.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let mut warnings = Vec::new();
+        let profiles = parse_profiles(&properties, &mut warnings);
+        assert_eq!(warnings, Vec::<String>::new());
+        profiles
+    }
+
+    fn save_and_fast() -> Vec<PerfProfile> {
+        profiles(&[
+            ("perf_profiles", "save,fast"),
+            ("perf.save.cpu_governor", "powersave"),
+            ("perf.save.devfreq_governor", "powersave"),
+            ("perf.fast.cpu_governor", "performance"),
+            ("perf.fast.cpu_min_freq", "1008000"),
+            ("perf.fast.policy4.max_freq", "1800000"),
+            ("perf.fast.devfreq.ff400000.gpu.governor", "performance"),
+        ])
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let defaults = profiles(&[]);
+        assert_eq!(defaults.iter().map(|profile| profile.name.as_str()).collect::<Vec<_>>(), vec!["norm", "max"]);
+        assert_eq!(defaults[0].hook.as_deref(), Some("perfnorm none"));
+        assert_eq!(defaults[1].hook.as_deref(), Some("perfmax none"));
+
+        let profiles = save_and_fast();
+        let fast = &profiles[1];
+        assert_eq!(fast.name, "fast");
+        assert_eq!(fast.cpu.governor.as_deref(), Some("performance"));
+        assert_eq!(fast.cpu.min_freq.as_deref(), Some("1008000"));
+        assert_eq!(fast.policies.len(), 1);
+        assert_eq!(fast.policies[0].0, "policy4");
+        assert_eq!(fast.policies[0].1.max_freq.as_deref(), Some("1800000"));
+        assert_eq!(fast.devfreq_devices, vec![("ff400000.gpu".to_string(), "performance".to_string())]);
+        assert_eq!(fast.hook, None);
+
+        let properties = [
+            ("perf_profiles", "max"),
+            ("perf.max.cpu_governor", "performance"),
+            ("perf.max.turbo", "on"),
+            ("perf.max.policy0.boost", "on"),
+            ("perf.max.hook", ""),
+            ("perf.eco.cpu_governor", "powersave"),
+        ];
+        let properties = properties.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let mut warnings = Vec::new();
+        let profiles = parse_profiles(&properties, &mut warnings);
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].hook, None);
+        assert_eq!(
+            warnings,
+            vec![
+                "Ignoring perf.eco.*: 'eco' isn't listed in perf_profiles",
+                "Ignoring perf.max.policy0.boost: unknown setting",
+                "Ignoring perf.max.turbo: unknown setting",
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_a_profile() {
+        let sysfs = FakeSysfs::new("apply", &["policy0", "policy4"], &["ff400000.gpu", "dmc"]);
+        let performance = sysfs.performance(save_and_fast());
+        assert_eq!(performance.active(), None);
+
+        performance.apply("fast").unwrap();
+        assert_eq!(performance.active().as_deref(), Some("fast"));
+        assert_eq!(sysfs.read("cpufreq/policy0/scaling_governor"), "performance");
+        assert_eq!(sysfs.read("cpufreq/policy0/scaling_min_freq"), "1008000");
+        assert_eq!(sysfs.read("cpufreq/policy0/scaling_max_freq"), "1512000");
+        assert_eq!(sysfs.read("cpufreq/policy4/scaling_max_freq"), "1800000");
+        assert_eq!(sysfs.read("devfreq/ff400000.gpu/governor"), "performance");
+        assert_eq!(sysfs.read("devfreq/dmc/governor"), "simple_ondemand");
+
+        // Found applied when starting over
+        assert_eq!(sysfs.performance(save_and_fast()).active().as_deref(), Some("fast"));
+
+        assert!(matches!(performance.apply("turbo"), Err(Error::Unknown { .. })));
+        assert_eq!(performance.active().as_deref(), Some("fast"));
+    }
+
+    #[test]
+    fn failed_profile_is_not_active() {
+        let sysfs = FakeSysfs::new("fail", &["policy0"], &["dmc"]);
+        let performance = sysfs.performance(save_and_fast());
+        performance.apply("save").unwrap();
+
+        // policy4 and the GPU are missing, the rest is still applied
+        match performance.apply("fast") {
+            Err(Error::PerfProfile { name, errors }) => {
+                assert_eq!(name, "fast");
+                assert_eq!(errors, vec!["no cpufreq policy4", "no devfreq device ff400000.gpu"]);
+            }
+            _ => panic!("applying 'fast' should fail"),
+        }
+        assert_eq!(sysfs.read("cpufreq/policy0/scaling_governor"), "performance");
+        assert_eq!(performance.active().as_deref(), Some("save"));
+
+        fs::remove_file(sysfs.0.join("devfreq/dmc/governor")).unwrap();
+        fs::create_dir(sysfs.0.join("devfreq/dmc/governor")).unwrap();
+        assert!(matches!(performance.apply("save"), Err(Error::PerfProfile { .. })));
+        assert_eq!(sysfs.read("cpufreq/policy0/scaling_governor"), "powersave");
+    }
+
+    #[test]
+    fn cycles_through_the_profiles() {
+        let sysfs = FakeSysfs::new("cycle", &["policy0", "policy4"], &["ff400000.gpu"]);
+        let performance = sysfs.performance(save_and_fast());
+        assert_eq!(performance.cycle().unwrap(), Some("save"));
+        assert_eq!(sysfs.read("cpufreq/policy4/scaling_governor"), "powersave");
+        assert_eq!(performance.cycle().unwrap(), Some("fast"));
+        assert_eq!(sysfs.read("cpufreq/policy4/scaling_governor"), "performance");
+        assert_eq!(performance.cycle().unwrap(), Some("save"));
+        assert_eq!(performance.active().as_deref(), Some("save"));
+
+        fs::remove_dir_all(sysfs.0.join("cpufreq/policy4")).unwrap();
+        assert!(performance.cycle().is_err());
+        assert_eq!(performance.active().as_deref(), Some("save"));
+
+        assert_eq!(sysfs.performance(Vec::new()).cycle().unwrap(), None);
+    }
+}
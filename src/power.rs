@@ -0,0 +1,152 @@
+use std::fs;
+use std::io;
+use std::process::Command;
+
+pub static SYS_POWER_STATE_FILE: &str = "/sys/power/state";
+
+/// Suspends or powers off the handheld.
+pub trait Power: Send + Sync {
+    fn suspend(&self) -> io::Result<()>;
+    fn power_off(&self) -> io::Result<()>;
+    /// How the system is suspended, for the logs.
+    fn describe(&self) -> String;
+}
+
+/// Asks systemd-logind over D-Bus, which needs no privileges from the
+/// active session.
+#[cfg(feature = "logind")]
+pub struct Logind {
+    connection: dbus::blocking::SyncConnection,
+}
+
+/// Writes `/sys/power/state` directly, for ogage running as root.
+/// Powering off still goes through `shutdown` so services stop cleanly.
+pub struct SysPowerState;
+
+/// Runs `systemctl suspend` and `shutdown` through a passwordless sudo, as
+/// older ogage versions did.
+pub struct Sudo;
+
+#[cfg(feature = "logind")]
+impl Logind {
+    pub fn connect() -> io::Result<Logind> {
+        let connection = dbus::blocking::SyncConnection::new_system().map_err(dbus_error)?;
+        let logind = Logind { connection };
+
+        // Make sure somebody answers before relying on it
+        let can_suspend: (String,) = logind.manager().method_call(LOGIND_MANAGER, "CanSuspend", ()).map_err(dbus_error)?;
+        if can_suspend.0 == "na" {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "logind can't suspend this system"));
+        }
+        Ok(logind)
+    }
+
+    fn manager(&self) -> dbus::blocking::Proxy<'_, &dbus::blocking::SyncConnection> {
+        self.connection
+            .with_proxy("org.freedesktop.login1", "/org/freedesktop/login1", std::time::Duration::from_secs(5))
+    }
+
+    fn call(&self, method: &str) -> io::Result<()> {
+        // Not interactive: fail rather than wait for a password prompt
+        self.manager().method_call(LOGIND_MANAGER, method, (false,)).map_err(dbus_error)
+    }
+}
+
+#[cfg(feature = "logind")]
+static LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+
+#[cfg(feature = "logind")]
+impl Power for Logind {
+    fn suspend(&self) -> io::Result<()> {
+        self.call("Suspend")
+    }
+
+    fn power_off(&self) -> io::Result<()> {
+        self.call("PowerOff")
+    }
+
+    fn describe(&self) -> String {
+        "logind".to_string()
+    }
+}
+
+#[cfg(feature = "logind")]
+fn dbus_error(e: dbus::Error) -> io::Error {
+    io::Error::other(e.message().unwrap_or("D-Bus error").to_string())
+}
+
+impl Power for SysPowerState {
+    fn suspend(&self) -> io::Result<()> {
+        // Returns once the system is back up
+        fs::write(SYS_POWER_STATE_FILE, "mem")
+    }
+
+    fn power_off(&self) -> io::Result<()> {
+        run(Command::new("shutdown").args(["-h", "now"]))
+    }
+
+    fn describe(&self) -> String {
+        SYS_POWER_STATE_FILE.to_string()
+    }
+}
+
+impl Power for Sudo {
+    fn suspend(&self) -> io::Result<()> {
+        run(Command::new("sudo").args(["systemctl", "suspend"]))
+    }
+
+    fn power_off(&self) -> io::Result<()> {
+        run(Command::new("sudo").args(["shutdown", "-h", "now"]))
+    }
+
+    fn describe(&self) -> String {
+        "sudo".to_string()
+    }
+}
+
+/// Picks the power backend from the `power` setting: `logind`, `sysfs` or
+/// `sudo`. Without a setting logind is used when it answers, then
+/// `/sys/power/state` when running as root, and sudo last.
+pub fn select(setting: Option<&str>) -> Box<dyn Power> {
+    match setting.map(|setting| setting.trim()) {
+        Some("sudo") => return Box::new(Sudo),
+        Some("sysfs") => return Box::new(SysPowerState),
+        Some("logind") => match connect_logind() {
+            Ok(power) => return power,
+            Err(e) => println!("Can't use logind ({}), falling back to sudo", e),
+        },
+        _ => {
+            match connect_logind() {
+                Ok(power) => return power,
+                Err(e) => println!("Can't use logind ({})", e),
+            }
+            if unsafe { libc::geteuid() } == 0 {
+                return Box::new(SysPowerState);
+            }
+        }
+    }
+
+    Box::new(Sudo)
+}
+
+#[cfg(feature = "logind")]
+fn connect_logind() -> io::Result<Box<dyn Power>> {
+    Ok(Box::new(Logind::connect()?))
+}
+
+#[cfg(not(feature = "logind"))]
+fn connect_logind() -> io::Result<Box<dyn Power>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "built without the logind feature"))
+}
+
+fn run(command: &mut Command) -> io::Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::devices::glob_match;
+
+pub static PROC_DIR: &str = "/proc";
+
+/// A rule for processes which keep the device awake while they run: a
+/// process name, or `cmdline:` and a pattern for the whole command line,
+/// its arguments separated by spaces. `*` matches any run of characters.
+#[derive(Clone, PartialEq)]
+pub enum ProcessRule {
+    Name(String),
+    Cmdline(String),
+}
+
+/// A running process which one of the rules matched.
+pub struct ProcessMatch {
+    pub rule: ProcessRule,
+    pub pid: u32,
+    pub name: String,
+}
+
+impl ProcessRule {
+    pub fn parse(rule: &str) -> ProcessRule {
+        let rule = rule.trim();
+        match rule.strip_prefix("cmdline:") {
+            Some(pattern) => ProcessRule::Cmdline(pattern.trim().to_string()),
+            None => ProcessRule::Name(rule.to_string()),
+        }
+    }
+
+    /// Parses a comma separated list of rules.
+    pub fn parse_list(rules: &str) -> Vec<ProcessRule> {
+        rules
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(ProcessRule::parse)
+            .collect()
+    }
+
+    /// The name is matched against the kernel's, which is cut to 15
+    /// characters, and against the program the process runs.
+    fn matches(&self, name: &str, args: &[&str]) -> bool {
+        match self {
+            ProcessRule::Name(pattern) => {
+                glob_match(pattern, name)
+                    || args
+                        .first()
+                        .and_then(|program| Path::new(program).file_name())
+                        .is_some_and(|program| glob_match(pattern, &program.to_string_lossy()))
+            }
+            ProcessRule::Cmdline(pattern) => !args.is_empty() && glob_match(pattern, &args.join(" ")),
+        }
+    }
+}
+
+impl fmt::Display for ProcessRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessRule::Name(pattern) => write!(f, "{}", pattern),
+            ProcessRule::Cmdline(pattern) => write!(f, "cmdline:{}", pattern),
+        }
+    }
+}
+
+/// Looks through the running processes in `proc`, usually `PROC_DIR`, for
+/// the first one a rule matches. Processes which end while being looked at
+/// are skipped.
+pub fn find(proc: &Path, rules: &[ProcessRule]) -> Option<ProcessMatch> {
+    if rules.is_empty() {
+        return None;
+    }
+
+    for entry in fs::read_dir(proc).ok()?.filter_map(|entry| entry.ok()) {
+        let pid = match entry.file_name().to_str().and_then(|pid| pid.parse::<u32>().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        let name = match fs::read_to_string(entry.path().join("comm")) {
+            Ok(name) => name.trim_end().to_string(),
+            Err(_) => continue,
+        };
+        // Kernel threads have an empty command line
+        let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+        let cmdline = String::from_utf8_lossy(&cmdline);
+        let args: Vec<&str> = cmdline.split('\0').filter(|arg| !arg.is_empty()).collect();
+
+        if let Some(rule) = rules.iter().find(|rule| rule.matches(&name, &args)) {
+            return Some(ProcessMatch { rule: rule.clone(), pid, name });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A /proc of processes in the temporary directory, removed when
+    /// dropped.
+    struct FakeProc(PathBuf);
+
+    impl FakeProc {
+        fn new(test: &str, processes: &[(&str, &str, &str)]) -> FakeProc {
+            let root = std::env::temp_dir().join(format!("ogage-proc-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("self")).unwrap();
+            for (pid, comm, cmdline) in processes {
+                let dir = root.join(pid);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("comm"), format!("{}\n", comm)).unwrap();
+                fs::write(dir.join("cmdline"), cmdline.replace(' ', "\0")).unwrap();
+            }
+            FakeProc(root)
+        }
+    }
+
+    impl Drop for FakeProc {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert!(ProcessRule::parse(" retroarch ") == ProcessRule::Name("retroarch".to_string()));
+        assert!(ProcessRule::parse("cmdline: mpv *.mkv") == ProcessRule::Cmdline("mpv *.mkv".to_string()));
+        assert_eq!(ProcessRule::parse("cmdline:*kodi*").to_string(), "cmdline:*kodi*");
+
+        let rules = ProcessRule::parse_list("retroarch, ,cmdline:python3 *download*,");
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0] == ProcessRule::Name("retroarch".to_string()));
+        assert!(rules[1] == ProcessRule::Cmdline("python3 *download*".to_string()));
+        assert!(ProcessRule::parse_list("").is_empty());
+    }
+
+    #[test]
+    fn matches_names_and_command_lines() {
+        let name = ProcessRule::parse("retroarch*");
+        assert!(name.matches("retroarch", &["/usr/bin/retroarch", "-L", "core.so"]));
+        // The kernel cuts the name, the program still matches
+        let long = ProcessRule::parse("emulationstation");
+        assert!(long.matches("emulationstatio", &["/usr/bin/emulationstation"]));
+        assert!(!long.matches("emulationstatio", &[]));
+        // Arguments aren't looked at for a name
+        assert!(!ProcessRule::parse("mpv").matches("sh", &["sh", "-c", "mpv"]));
+
+        let cmdline = ProcessRule::parse("cmdline:*python3 *download.py*");
+        assert!(cmdline.matches("python3", &["/usr/bin/python3", "/opt/download.py", "--all"]));
+        assert!(!cmdline.matches("python3", &["/usr/bin/python3", "/opt/upload.py"]));
+        // Kernel threads have no command line
+        assert!(!ProcessRule::parse("cmdline:*").matches("kworker/0:1", &[]));
+    }
+
+    #[test]
+    fn finds_a_matching_process() {
+        let proc = FakeProc::new(
+            "find",
+            &[("1", "init", "/sbin/init"), ("12", "kthreadd", ""), ("345", "python3", "/usr/bin/python3 /opt/download.py")],
+        );
+        assert!(find(&proc.0, &[]).is_none());
+        assert!(find(&proc.0, &ProcessRule::parse_list("retroarch,cmdline:*upload*")).is_none());
+
+        let found = find(&proc.0, &ProcessRule::parse_list("retroarch,cmdline:*download*")).unwrap();
+        assert_eq!(found.pid, 345);
+        assert_eq!(found.name, "python3");
+        assert_eq!(found.rule.to_string(), "cmdline:*download*");
+
+        assert_eq!(find(&proc.0, &ProcessRule::parse_list("kthread*")).unwrap().pid, 12);
+        assert!(find(&proc.0.join("missing"), &ProcessRule::parse_list("init")).is_none());
+    }
+}
@@ -0,0 +1,309 @@
+use evdev::enums::{EventCode, EventType, EV_KEY};
+use props_rs::*;
+use std::fs;
+use std::path::Path;
+
+use crate::action::{Action, ACTIONS};
+use crate::bindings::{Binding, Trigger};
+use crate::devices::{glob_match, input_ids, DeviceRule};
+use crate::error::Error;
+
+pub static PROFILES_DIR: &str = "/usr/local/etc/ogage/devices";
+static DT_MODEL_FILE: &str = "/proc/device-tree/model";
+static DT_COMPATIBLE_FILE: &str = "/proc/device-tree/compatible";
+static FALLBACK_PROFILE: &str = "ogs";
+
+static BUILTIN_PROFILES: [(&str, &str); 4] = [
+    ("oga1.conf", include_str!("../devices/oga1.conf")),
+    ("ogs.conf", include_str!("../devices/ogs.conf")),
+    ("rgb10maxtop.conf", include_str!("../devices/rgb10maxtop.conf")),
+    ("rgb10maxnative.conf", include_str!("../devices/rgb10maxnative.conf")),
+];
+
+/// Button layout of a handheld: which key is the hotkey and which button
+/// triggers each action.
+///
+/// Profiles are property files with a `name`, the `hotkey`, the `powerkey`,
+/// one `<action>=<key code>` line per bound action and an optional
+/// `without_hotkey` list of actions that fire without holding the hotkey.
+/// `device_names` lists the RetroOZ device names (`*` wildcards allowed)
+/// the profile is used for and defaults to its name. `models` and
+/// `compatible` are matched against the device tree, `joypads` against the
+/// name or `vendor:product` ID of the input devices.
+pub struct DeviceProfile {
+    pub name: String,
+    /// How the profile was chosen, for the logs.
+    pub detected_by: String,
+    pub device_names: Vec<String>,
+    pub models: Vec<String>,
+    pub compatible: Vec<String>,
+    pub joypads: Vec<DeviceRule>,
+    pub hotkey: EventCode,
+    pub powerkey: EventCode,
+    pub bindings: Vec<Binding>,
+}
+
+impl DeviceProfile {
+    /// Picks the profile of the handheld among the built-in profiles and
+    /// the ones found in `/usr/local/etc/ogage/devices`, the latter
+    /// replacing built-in profiles of the same name.
+    ///
+    /// The RetroOZ device name wins when given, then comes the device tree
+    /// and last the name and IDs of the joypad.
+    pub fn detect(device: Option<&str>) -> DeviceProfile {
+        let mut profiles = load_all();
+
+        let mut found = device.and_then(|device| {
+            let index = profiles
+                .iter()
+                .position(|profile| profile.device_names.iter().any(|pattern| glob_match(pattern, device)));
+            if index.is_none() {
+                println!("No device profile for RetroOZ device '{}'", device);
+            }
+            index.map(|index| (index, format!("RetroOZ device '{}'", device)))
+        });
+
+        if found.is_none() {
+            if let Some(model) = read_dt_strings(DT_MODEL_FILE).into_iter().next() {
+                found = profiles
+                    .iter()
+                    .position(|profile| profile.models.iter().any(|pattern| glob_match(pattern, &model)))
+                    .map(|index| (index, format!("device tree model '{}'", model)));
+            }
+        }
+
+        if found.is_none() {
+            found = read_dt_strings(DT_COMPATIBLE_FILE).iter().find_map(|compatible| {
+                profiles
+                    .iter()
+                    .position(|profile| profile.compatible.iter().any(|pattern| glob_match(pattern, compatible)))
+                    .map(|index| (index, format!("device tree compatible '{}'", compatible)))
+            });
+        }
+
+        if found.is_none() {
+            found = input_ids().iter().find_map(|(name, vendor, product)| {
+                profiles
+                    .iter()
+                    .position(|profile| profile.joypads.iter().any(|rule| rule.matches_id(name, *vendor, *product)))
+                    .map(|index| (index, format!("joypad '{}' {:04x}:{:04x}", name, vendor, product)))
+            });
+        }
+
+        let (index, detected_by) = found
+            .or_else(|| {
+                profiles
+                    .iter()
+                    .position(|profile| profile.name == FALLBACK_PROFILE)
+                    .map(|index| (index, "fallback".to_string()))
+            })
+            .expect("Built-in fallback device profile is missing");
+
+        let mut profile = profiles.swap_remove(index);
+        profile.detected_by = detected_by;
+        println!("Device profile '{}' chosen by {}", profile.name, profile.detected_by);
+        profile
+    }
+
+    pub fn parse(source: &str, text: &str) -> Result<DeviceProfile, Error> {
+        let parsed = parse(text.as_bytes()).map_err(|_| Error::ConfigSyntax { file: source.to_string() })?;
+        let properties = to_map(parsed);
+        let invalid = |message: &str| Error::Profile {
+            file: source.to_string(),
+            message: message.to_string(),
+        };
+
+        let name = properties
+            .get("name")
+            .map(|name| name.trim().to_string())
+            .ok_or_else(|| invalid("no name"))?;
+        let device_names = match properties.get("device_names") {
+            Some(names) => split_list(names),
+            None => vec![name.clone()],
+        };
+        let list = |property: &str| properties.get(property).map(|list| split_list(list)).unwrap_or_default();
+        let models = list("models");
+        let compatible = list("compatible");
+        let joypads = list("joypads").iter().map(|rule| DeviceRule::parse(rule)).collect();
+        let key = |property: &str| -> Result<Option<EventCode>, Error> {
+            match properties.get(property) {
+                Some(value) => parse_key_code(value).map(Some).ok_or_else(|| Error::ConfigValue {
+                    file: source.to_string(),
+                    key: property.to_string(),
+                    value: value.trim().to_string(),
+                    expected: "a key code",
+                }),
+                None => Ok(None),
+            }
+        };
+
+        let hotkey = key("hotkey")?.ok_or_else(|| invalid("no hotkey"))?;
+        let powerkey = key("powerkey")?.unwrap_or(EventCode::EV_KEY(EV_KEY::KEY_POWER));
+
+        let mut without_hotkey = Vec::new();
+        if let Some(list) = properties.get("without_hotkey") {
+            for name in split_list(list) {
+                let action = Action::from_name(&name)
+                    .ok_or_else(|| invalid(&format!("unknown action '{}' in without_hotkey", name)))?;
+                without_hotkey.push(action);
+            }
+        }
+
+        let mut bindings = Vec::new();
+        for action in ACTIONS.iter() {
+            if let Some(code) = key(action.name())? {
+                bindings.push(Binding {
+                    action: *action,
+                    keys: vec![code],
+                    with_hotkey: !without_hotkey.contains(action),
+                    trigger: Trigger::Press,
+                });
+            }
+        }
+
+        Ok(DeviceProfile {
+            name,
+            detected_by: String::new(),
+            device_names,
+            models,
+            compatible,
+            joypads,
+            hotkey,
+            powerkey,
+            bindings,
+        })
+    }
+
+    /// Replaces the bindings of the profile by the user's ones; `None`
+    /// removes the binding of the action.
+    pub fn apply_overrides(&mut self, overrides: Vec<(Action, Option<Binding>)>) {
+        for (action, binding) in overrides {
+            self.bindings.retain(|b| b.action != action);
+            if let Some(binding) = binding {
+                self.bindings.push(binding);
+            }
+        }
+    }
+
+    /// Every key code the profile reacts to.
+    pub fn codes(&self) -> Vec<EventCode> {
+        let mut codes = vec![self.hotkey.clone(), self.powerkey.clone()];
+        codes.extend(self.bindings.iter().flat_map(|binding| binding.keys.iter().cloned()));
+        codes
+    }
+}
+
+pub fn parse_key_code(name: &str) -> Option<EventCode> {
+    EventCode::from_str(&EventType::EV_KEY, name.trim())
+}
+
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Device tree properties are lists of NUL terminated strings.
+fn read_dt_strings(path: &str) -> Vec<String> {
+    fs::read(path)
+        .map(|bytes| {
+            bytes
+                .split(|b| *b == 0)
+                .filter(|s| !s.is_empty())
+                .map(|s| String::from_utf8_lossy(s).trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn load_all() -> Vec<DeviceProfile> {
+    let mut profiles: Vec<DeviceProfile> = Vec::new();
+
+    // Installed profiles go first so they win over the built-in ones
+    if let Ok(entries) = fs::read_dir(PROFILES_DIR) {
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            match read_profile(&path) {
+                Ok(profile) => {
+                    println!("Loaded device profile '{}' from {}", profile.name, path.display());
+                    profiles.push(profile);
+                }
+                Err(e) => println!("Ignoring {}", e),
+            }
+        }
+    }
+
+    for (file, text) in BUILTIN_PROFILES.iter() {
+        let profile = DeviceProfile::parse(file, text).expect("Invalid built-in device profile");
+        if !profiles.iter().any(|p| p.name == profile.name) {
+            profiles.push(profile);
+        }
+    }
+
+    profiles
+}
+
+fn read_profile(path: &Path) -> Result<DeviceProfile, Error> {
+    let file = path.display().to_string();
+    let text = fs::read_to_string(path).map_err(|e| Error::file(&file, e))?;
+    DeviceProfile::parse(&file, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_profiles_parse() {
+        for (file, text) in BUILTIN_PROFILES.iter() {
+            let profile = DeviceProfile::parse(file, text).unwrap();
+            assert!(!profile.bindings.is_empty(), "{} has no bindings", file);
+        }
+    }
+
+    #[test]
+    fn parses_a_profile() {
+        let text = "name=test\n\
+                    models=*Test One*, *Test Two*\n\
+                    joypads=1209:0a3f,Test Pad*\n\
+                    hotkey=BTN_MODE\n\
+                    vol_up=BTN_NORTH\n\
+                    suspend=KEY_POWER\n\
+                    without_hotkey=suspend\n";
+        let profile = DeviceProfile::parse("test.conf", text).unwrap();
+        assert_eq!(profile.name, "test");
+        assert_eq!(profile.device_names, vec!["test"]);
+        assert_eq!(profile.models, vec!["*Test One*", "*Test Two*"]);
+        assert!(profile.compatible.is_empty());
+        assert_eq!(profile.joypads.len(), 2);
+        assert_eq!(profile.hotkey, EventCode::EV_KEY(EV_KEY::BTN_MODE));
+        assert_eq!(profile.powerkey, EventCode::EV_KEY(EV_KEY::KEY_POWER));
+
+        let binding = |action| profile.bindings.iter().find(|binding| binding.action == action).unwrap();
+        assert!(binding(Action::VolUp).with_hotkey);
+        assert!(!binding(Action::Suspend).with_hotkey);
+        assert_eq!(binding(Action::Suspend).keys, vec![EventCode::EV_KEY(EV_KEY::KEY_POWER)]);
+    }
+
+    #[test]
+    fn rejects_bad_profiles() {
+        let error = |text: &str| DeviceProfile::parse("test.conf", text).err().unwrap().to_string();
+        assert_eq!(error("hotkey=BTN_MODE\n"), "device profile test.conf: no name");
+        assert_eq!(error("name=test\n"), "device profile test.conf: no hotkey");
+        assert_eq!(
+            error("name=test\nhotkey=BTN_MODE\nvol_up=BTN_NOPE\n"),
+            "test.conf: vol_up='BTN_NOPE' should be a key code"
+        );
+        assert_eq!(
+            error("name=test\nhotkey=BTN_MODE\nwithout_hotkey=explode\n"),
+            "device profile test.conf: unknown action 'explode' in without_hotkey"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+
+pub static RFKILL_DEVICE: &str = "/dev/rfkill";
+
+// From <linux/rfkill.h>
+const RFKILL_TYPE_WLAN: u8 = 1;
+const RFKILL_TYPE_BLUETOOTH: u8 = 2;
+const RFKILL_OP_ADD: u8 = 0;
+const RFKILL_OP_DEL: u8 = 1;
+const RFKILL_OP_CHANGE: u8 = 2;
+const RFKILL_OP_CHANGE_ALL: u8 = 3;
+const RFKILL_EVENT_SIZE: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RadioKind {
+    Wifi,
+    Bluetooth,
+}
+
+/// Wireless radio switches.
+pub trait Radios: Send + Sync {
+    fn set_enabled(&self, kind: RadioKind, enabled: bool) -> io::Result<()>;
+    /// Whether any radio of the kind is on, `None` when there is none.
+    fn enabled(&self, kind: RadioKind) -> io::Result<Option<bool>>;
+    /// What the radios are switched with, for the logs.
+    fn describe(&self) -> String;
+}
+
+/// Soft-blocks and unblocks radios through `/dev/rfkill`.
+pub struct Rfkill;
+
+/// Runs `nmcli`, which only knows about Wi-Fi.
+pub struct Nmcli;
+
+impl RadioKind {
+    fn rfkill_type(self) -> u8 {
+        match self {
+            RadioKind::Wifi => RFKILL_TYPE_WLAN,
+            RadioKind::Bluetooth => RFKILL_TYPE_BLUETOOTH,
+        }
+    }
+}
+
+impl fmt::Display for RadioKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RadioKind::Wifi => "Wi-Fi",
+            RadioKind::Bluetooth => "Bluetooth",
+        })
+    }
+}
+
+impl Rfkill {
+    /// Reads the state of every radio, keyed by rfkill index: its type and
+    /// whether it is blocked.
+    fn radios() -> io::Result<HashMap<u32, (u8, bool)>> {
+        let mut file = open_rfkill(false)?;
+        let mut radios = HashMap::new();
+
+        // Opening replays an ADD event per radio, then changes follow
+        loop {
+            let mut event = [0u8; RFKILL_EVENT_SIZE];
+            match file.read(&mut event) {
+                Ok(read) if read >= RFKILL_EVENT_SIZE => {
+                    let idx = u32::from_ne_bytes([event[0], event[1], event[2], event[3]]);
+                    let (type_, op, soft, hard) = (event[4], event[5], event[6], event[7]);
+                    match op {
+                        RFKILL_OP_ADD | RFKILL_OP_CHANGE => {
+                            radios.insert(idx, (type_, soft != 0 || hard != 0));
+                        }
+                        RFKILL_OP_DEL => {
+                            radios.remove(&idx);
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(_) => return Ok(radios),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(radios),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Radios for Rfkill {
+    fn set_enabled(&self, kind: RadioKind, enabled: bool) -> io::Result<()> {
+        let mut event = [0u8; RFKILL_EVENT_SIZE];
+        event[4] = kind.rfkill_type();
+        event[5] = RFKILL_OP_CHANGE_ALL;
+        event[6] = if enabled { 0 } else { 1 };
+        open_rfkill(true)?.write_all(&event)
+    }
+
+    fn enabled(&self, kind: RadioKind) -> io::Result<Option<bool>> {
+        let blocked: Vec<bool> = Rfkill::radios()?
+            .values()
+            .filter(|(type_, _)| *type_ == kind.rfkill_type())
+            .map(|(_, blocked)| *blocked)
+            .collect();
+
+        if blocked.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(blocked.iter().any(|blocked| !blocked)))
+    }
+
+    fn describe(&self) -> String {
+        RFKILL_DEVICE.to_string()
+    }
+}
+
+impl Radios for Nmcli {
+    fn set_enabled(&self, kind: RadioKind, enabled: bool) -> io::Result<()> {
+        if kind != RadioKind::Wifi {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("nmcli can't switch {}", kind)));
+        }
+        let output = Command::new("nmcli")
+            .args(["radio", "wifi", if enabled { "on" } else { "off" }])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "nmcli {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    fn enabled(&self, kind: RadioKind) -> io::Result<Option<bool>> {
+        if kind != RadioKind::Wifi {
+            return Ok(None);
+        }
+        let output = Command::new("nmcli").args(["radio", "wifi"]).output()?;
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "enabled" => Ok(Some(true)),
+            "disabled" => Ok(Some(false)),
+            _ => Ok(None),
+        }
+    }
+
+    fn describe(&self) -> String {
+        "nmcli".to_string()
+    }
+}
+
+/// Picks the radio switch from the `radio` setting: `nmcli`, or nothing for
+/// rfkill. nmcli stands in when `/dev/rfkill` can't be opened.
+pub fn select(setting: Option<&str>) -> Box<dyn Radios> {
+    if setting.map(|setting| setting.trim()) == Some("nmcli") {
+        return Box::new(Nmcli);
+    }
+
+    match open_rfkill(true) {
+        Ok(_) => Box::new(Rfkill),
+        Err(e) => {
+            println!("Can't open {} ({}), falling back to nmcli", RFKILL_DEVICE, e);
+            Box::new(Nmcli)
+        }
+    }
+}
+
+fn open_rfkill(write: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .read(!write)
+        .write(write)
+        .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+        .open(RFKILL_DEVICE)
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+/// How the incremental actions repeat while their buttons stay held.
+#[derive(Clone)]
+pub struct RepeatSettings {
+    /// Wait before the first repeat.
+    pub delay: Duration,
+    /// Time between the first repeats.
+    pub interval: Duration,
+    /// With acceleration each repeat comes sooner, down to this interval.
+    pub min_interval: Duration,
+}
+
+/// The action of a held binding, fired again on a timer until its chord
+/// is let go.
+pub struct Repeat {
+    /// Index of the binding being repeated.
+    pub index: usize,
+    next: Instant,
+    interval: Duration,
+}
+
+impl Repeat {
+    pub fn start(index: usize, settings: &RepeatSettings) -> Repeat {
+        Repeat {
+            index,
+            next: Instant::now() + settings.delay,
+            interval: settings.interval,
+        }
+    }
+
+    /// Time left until the next repeat.
+    pub fn next_timeout(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether a repeat is due, scheduling the following one if so.
+    pub fn due(&mut self, settings: &RepeatSettings) -> bool {
+        let now = Instant::now();
+        if self.next > now {
+            return false;
+        }
+
+        // Don't catch up on repeats missed while an action was running
+        self.next = now + self.interval;
+        self.interval = (self.interval * 4 / 5).max(settings.min_interval);
+        true
+    }
+}
@@ -0,0 +1,68 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+/// Signals read from a descriptor, meant to be registered on the main
+/// `mio::Poll` instead of interrupting it. The signals are blocked, which
+/// only holds for threads started afterwards: create it first thing.
+pub struct SignalFd {
+    fd: RawFd,
+}
+
+impl SignalFd {
+    pub fn new(signals: &[libc::c_int]) -> io::Result<SignalFd> {
+        let fd = unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for signal in signals {
+                libc::sigaddset(&mut set, *signal);
+            }
+            if libc::sigprocmask(libc::SIG_BLOCK, &set, ptr::null_mut()) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(SignalFd { fd })
+    }
+
+    /// Reads every pending signal.
+    pub fn read_signals(&self) -> io::Result<Vec<libc::c_int>> {
+        let mut signals = Vec::new();
+
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of::<libc::signalfd_siginfo>();
+            let len = unsafe { libc::read(self.fd, &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void, size) };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(signals);
+                }
+                return Err(err);
+            }
+            if (len as usize) < size {
+                return Ok(signals);
+            }
+            signals.push(info.ssi_signo as libc::c_int);
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}